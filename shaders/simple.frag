@@ -2,16 +2,38 @@
 
 in vec3 v_position;
 in vec3 v_normal;
+in vec3 v_tex_weights;
+in vec2 v_tex_uv[3];
+in vec2 v_tex_coord;
+in vec4 v_color;
+in float v_occlusion;
 uniform vec4 u_color;
+uniform int u_vertex_colors;
+// Line geometry has no normals, so is drawn in u_color as it is
+uniform int u_unlit;
 uniform mat4 u_model;
 uniform mat4 u_view;
 uniform mat4 u_mvp;
 
 out vec4 color;
 
+// Procedural stand-in for a texture
+float checker(vec2 uv)
+{
+    vec2 f = floor(uv);
+    return mod(f.x + f.y, 2.0);
+}
+
 void main()
 {
-    vec4 col = u_color;
+    if (u_unlit != 0) {
+        color = u_color;
+        return;
+    }
+    vec4 col = u_vertex_colors != 0 ? v_color : u_color;
+    float tex = dot(v_tex_weights, vec3(checker(v_tex_uv[0]), checker(v_tex_uv[1]), checker(v_tex_uv[2])))
+        + checker(v_tex_coord);
+    col.xyz *= 1.0 - 0.3 * tex;
     vec3 light_dir = normalize(vec3(1.0, 2.0, 1.0));
     vec3 normal = transpose(inverse(mat3(u_model))) * v_normal;
     //vec3 normal = v_normal;
@@ -24,5 +46,5 @@ void main()
         diffuse = (1.0 - diffuse);
         col.xyz = vec3(0.0, 0.0, 0.8);
     }
-    color = vec4(col.xyz * 0.2 + col.xyz * diffuse, 1.0); //vec4(1.0f, 0.0f, 1.0f, 1.0f);
+    color = vec4(col.xyz * 0.2 * v_occlusion + col.xyz * diffuse, 1.0); //vec4(1.0f, 0.0f, 1.0f, 1.0f);
 }
\ No newline at end of file
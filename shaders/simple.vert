@@ -1,9 +1,29 @@
 #version 460 core
 
-in vec3 position;
-in vec3 normal;
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+// Triplanar texture coordinates, see mc::TexturePolicy. Weights are zero
+// when the attributes are not enabled, which leaves the mesh untextured
+layout(location = 2) in vec3 tex_weights;
+layout(location = 3) in vec2 tex_uv_x;
+layout(location = 4) in vec2 tex_uv_y;
+layout(location = 5) in vec2 tex_uv_z;
+// Per-vertex colour, used when u_vertex_colors is set
+layout(location = 6) in vec4 color;
+// Baked ambient visibility, see Mesh::bake_occlusion. Where the attribute is
+// not enabled the current value set with glVertexAttrib1f is used instead
+layout(location = 7) in float occlusion;
+// Plain texture coordinates, see mc::TexturePolicy::DominantAxis. The
+// current value (0, 0) when the attribute is not enabled leaves the mesh
+// untextured
+layout(location = 8) in vec2 tex_coord;
 out vec3 v_position;
 out vec3 v_normal;
+out vec3 v_tex_weights;
+out vec2 v_tex_uv[3];
+out vec2 v_tex_coord;
+out vec4 v_color;
+out float v_occlusion;
 uniform mat4 u_mvp;
 uniform float u_aspect;
 
@@ -11,5 +31,10 @@ void main()
 {
     v_position = position;
     v_normal = normal;
+    v_tex_weights = tex_weights;
+    v_tex_uv = vec2[3](tex_uv_x, tex_uv_y, tex_uv_z);
+    v_tex_coord = tex_coord;
+    v_color = color;
+    v_occlusion = occlusion;
     gl_Position = u_mvp * vec4(position, 1.0f);
 }
\ No newline at end of file
@@ -0,0 +1,488 @@
+// Bounding volume hierarchy over mesh triangles
+
+use crate::mc::Mesh;
+use crate::ray::{face_normal, ray_aabb, ray_triangle, HitId, Ray, RayHit};
+
+/// Subtrees with more triangles than this are built on separate rayon
+/// tasks when building in parallel
+const PARALLEL_THRESHOLD: usize = 4096;
+
+/// How splits are chosen, both minimising the surface area heuristic
+#[allow(unused)]
+#[derive(Clone, Copy, Debug)]
+pub enum BvhBuild {
+    /// Evaluate every split position along each axis. Best trees, slowest
+    Sweep,
+    /// Evaluate splits between this many equal bins along each axis
+    Binned(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BvhOptions {
+    pub build: BvhBuild,
+    /// Nodes with this many triangles or fewer always become leaves
+    pub leaf_size: usize,
+    /// Build subtrees concurrently with rayon
+    pub parallel: bool,
+}
+impl Default for BvhOptions {
+    fn default() -> Self {
+        BvhOptions {
+            build: BvhBuild::Binned(16),
+            leaf_size: 4,
+            parallel: true,
+        }
+    }
+}
+
+/// Node in depth-first order. The first child of an inner node directly
+/// follows it
+#[derive(Clone, Copy, Debug)]
+pub struct Node {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+    /// Leaves: first entry in `Bvh::order`. Inner nodes: index of the
+    /// second child
+    pub offset: u32,
+    /// Number of triangles, 0 for inner nodes
+    pub count: u32,
+}
+
+/// Closest point on the mesh to a query point
+#[allow(unused)]
+#[derive(Clone, Copy, Debug)]
+pub struct ClosestPoint {
+    pub point: glm::Vec3,
+    pub distance: f32,
+    pub triangle: u32,
+}
+
+pub struct Bvh {
+    pub nodes: Vec<Node>,
+    /// Triangle indices in leaf order
+    pub order: Vec<u32>,
+    triangles: Vec<[glm::Vec3; 3]>,
+}
+
+fn surface_area(min: &glm::Vec3, max: &glm::Vec3) -> f32 {
+    let d = (max - min).map(|x| x.max(0.0));
+    2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+}
+
+fn grow(b: &mut (glm::Vec3, glm::Vec3), min: &glm::Vec3, max: &glm::Vec3) {
+    b.0 = glm::min2(&b.0, min);
+    b.1 = glm::max2(&b.1, max);
+}
+
+fn empty_bounds() -> (glm::Vec3, glm::Vec3) {
+    (glm::Vec3::repeat(f32::INFINITY), glm::Vec3::repeat(f32::NEG_INFINITY))
+}
+
+/// Per-triangle data used while building
+struct BuildInput {
+    bounds: Vec<(glm::Vec3, glm::Vec3)>,
+    centroids: Vec<glm::Vec3>,
+}
+
+/// Best split as (axis, centroid coordinate threshold, SAH cost)
+fn find_split(prims: &[u32], input: &BuildInput, build: BvhBuild, area: f32) -> Option<(usize, f32, f32)> {
+    let mut cmin = glm::Vec3::repeat(f32::INFINITY);
+    let mut cmax = glm::Vec3::repeat(f32::NEG_INFINITY);
+    for &p in prims {
+        cmin = glm::min2(&cmin, &input.centroids[p as usize]);
+        cmax = glm::max2(&cmax, &input.centroids[p as usize]);
+    }
+    let mut best: Option<(usize, f32, f32)> = None;
+    let mut consider = |axis: usize, threshold: f32, cost: f32| {
+        if best.is_none_or(|b| cost < b.2) {
+            best = Some((axis, threshold, cost));
+        }
+    };
+    for axis in 0..3 {
+        if cmax[axis] <= cmin[axis] { continue }
+        match build {
+            BvhBuild::Sweep => {
+                let mut sorted = prims.to_vec();
+                sorted.sort_unstable_by(|a, b| input.centroids[*a as usize][axis]
+                    .total_cmp(&input.centroids[*b as usize][axis]));
+                // Area of the right side for each split, swept from the end
+                let mut right = vec![0.0; sorted.len()];
+                let mut b = empty_bounds();
+                for i in (1..sorted.len()).rev() {
+                    let (min, max) = &input.bounds[sorted[i] as usize];
+                    grow(&mut b, min, max);
+                    right[i] = surface_area(&b.0, &b.1);
+                }
+                let mut b = empty_bounds();
+                for i in 1..sorted.len() {
+                    let (min, max) = &input.bounds[sorted[i - 1] as usize];
+                    grow(&mut b, min, max);
+                    let (c0, c1) = (
+                        input.centroids[sorted[i - 1] as usize][axis],
+                        input.centroids[sorted[i] as usize][axis],
+                    );
+                    // Only split between distinct centroids, so partitioning
+                    // by threshold reproduces this split
+                    if c0 == c1 { continue }
+                    let cost = 1.0 + (surface_area(&b.0, &b.1) * i as f32
+                        + right[i] * (sorted.len() - i) as f32) / area;
+                    consider(axis, c1, cost);
+                }
+            },
+            BvhBuild::Binned(bins) => {
+                let bins = bins.max(2);
+                let extent = cmax[axis] - cmin[axis];
+                let bin_of = |c: f32| (((c - cmin[axis]) / extent * bins as f32) as usize).min(bins - 1);
+                let mut bounds = vec![empty_bounds(); bins];
+                let mut counts = vec![0usize; bins];
+                for &p in prims {
+                    let i = bin_of(input.centroids[p as usize][axis]);
+                    let (min, max) = &input.bounds[p as usize];
+                    grow(&mut bounds[i], min, max);
+                    counts[i] += 1;
+                }
+                let mut right = vec![(0.0, 0); bins];
+                let (mut b, mut n) = (empty_bounds(), 0);
+                for i in (1..bins).rev() {
+                    grow(&mut b, &bounds[i].0, &bounds[i].1);
+                    n += counts[i];
+                    right[i] = (surface_area(&b.0, &b.1), n);
+                }
+                let (mut b, mut n) = (empty_bounds(), 0);
+                for i in 1..bins {
+                    grow(&mut b, &bounds[i - 1].0, &bounds[i - 1].1);
+                    n += counts[i - 1];
+                    if n == 0 || right[i].1 == 0 { continue }
+                    let cost = 1.0 + (surface_area(&b.0, &b.1) * n as f32
+                        + right[i].0 * right[i].1 as f32) / area;
+                    consider(axis, cmin[axis] + extent * i as f32 / bins as f32, cost);
+                }
+            },
+        }
+    }
+    best
+}
+
+/// Build the subtree over `prims`, which start at `start` in the final
+/// order. Node indices in the result are relative to its first node
+fn build_node(prims: &mut [u32], start: usize, input: &BuildInput, opts: &BvhOptions) -> Vec<Node> {
+    let mut b = empty_bounds();
+    for &p in prims.iter() {
+        let (min, max) = &input.bounds[p as usize];
+        grow(&mut b, min, max);
+    }
+    let leaf = vec![Node { min: b.0, max: b.1, offset: start as u32, count: prims.len() as u32 }];
+    if prims.len() <= opts.leaf_size { return leaf }
+
+    let (axis, threshold, cost) = match find_split(prims, input, opts.build, surface_area(&b.0, &b.1)) {
+        Some(s) => s,
+        None => return leaf,
+    };
+    // Cost of intersecting every triangle directly, relative to a traversal step
+    if cost >= prims.len() as f32 && prims.len() <= 4 * opts.leaf_size { return leaf }
+
+    // Partition by centroid
+    let mut mid = 0;
+    for i in 0..prims.len() {
+        if input.centroids[prims[i] as usize][axis] < threshold {
+            prims.swap(i, mid);
+            mid += 1;
+        }
+    }
+    if mid == 0 || mid == prims.len() { return leaf }
+
+    let parallel = opts.parallel && prims.len() > PARALLEL_THRESHOLD;
+    let (left, right) = prims.split_at_mut(mid);
+    let (left, right) = if parallel {
+        rayon::join(
+            || build_node(left, start, input, opts),
+            || build_node(right, start + mid, input, opts),
+        )
+    } else {
+        (build_node(left, start, input, opts), build_node(right, start + mid, input, opts))
+    };
+
+    let mut nodes = Vec::with_capacity(1 + left.len() + right.len());
+    let second = 1 + left.len() as u32;
+    nodes.push(Node { min: b.0, max: b.1, offset: second, count: 0 });
+    for (base, subtree) in [(1, left), (second, right)] {
+        nodes.extend(subtree.into_iter().map(|mut n| {
+            if n.count == 0 { n.offset += base }
+            n
+        }));
+    }
+    nodes
+}
+
+/// Closest point to `p` on triangle `t` (Ericson, Real-Time Collision
+/// Detection, 5.1.5)
+pub fn closest_point_on_triangle(p: &glm::Vec3, t: &[glm::Vec3; 3]) -> glm::Vec3 {
+    let [a, b, c] = *t;
+    let (ab, ac, ap) = (b - a, c - a, p - a);
+    let (d1, d2) = (glm::dot(&ab, &ap), glm::dot(&ac, &ap));
+    if d1 <= 0.0 && d2 <= 0.0 { return a }
+    let bp = p - b;
+    let (d3, d4) = (glm::dot(&ab, &bp), glm::dot(&ac, &bp));
+    if d3 >= 0.0 && d4 <= d3 { return b }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3))
+    }
+    let cp = p - c;
+    let (d5, d6) = (glm::dot(&ab, &cp), glm::dot(&ac, &cp));
+    if d6 >= 0.0 && d5 <= d6 { return c }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6))
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)))
+    }
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+/// Separating axis test between a triangle and an axis-aligned box
+/// (Akenine-Möller, "Fast 3D Triangle-Box Overlap Testing")
+pub fn triangle_overlaps_aabb(t: &[glm::Vec3; 3], min: &glm::Vec3, max: &glm::Vec3) -> bool {
+    let center = (min + max) * 0.5;
+    let half = (max - min) * 0.5;
+    let v = t.map(|p| p - center);
+    let edges = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+    let separated = |axis: &glm::Vec3| {
+        let p = v.map(|p| glm::dot(&p, axis));
+        let r = half.x * axis.x.abs() + half.y * axis.y.abs() + half.z * axis.z.abs();
+        p.iter().cloned().fold(f32::INFINITY, f32::min) > r
+            || p.iter().cloned().fold(f32::NEG_INFINITY, f32::max) < -r
+    };
+    // Box face normals, then the triangle normal, then edge cross products
+    let mut axes = vec![glm::Vec3::x(), glm::Vec3::y(), glm::Vec3::z(), glm::cross(&edges[0], &edges[1])];
+    for e in edges.iter() {
+        for u in [glm::Vec3::x(), glm::Vec3::y(), glm::Vec3::z()] {
+            axes.push(glm::cross(&u, e));
+        }
+    }
+    !axes.iter().any(|a| glm::length2(a) > 0.0 && separated(a))
+}
+
+fn aabb_distance2(p: &glm::Vec3, min: &glm::Vec3, max: &glm::Vec3) -> f32 {
+    let d = glm::max2(&(min - p), &(p - max)).map(|x| x.max(0.0));
+    glm::length2(&d)
+}
+
+#[allow(unused)]
+impl Bvh {
+    pub fn build(mesh: &Mesh, opts: &BvhOptions) -> Bvh {
+        let triangles = mesh.indices.chunks_exact(3)
+            .map(|t| [mesh.position(t[0]), mesh.position(t[1]), mesh.position(t[2])])
+            .collect::<Vec<_>>();
+        let input = BuildInput {
+            bounds: triangles.iter()
+                .map(|t| (glm::min3(&t[0], &t[1], &t[2]), glm::max3(&t[0], &t[1], &t[2])))
+                .collect(),
+            centroids: triangles.iter().map(|t| (t[0] + t[1] + t[2]) / 3.0).collect(),
+        };
+        let mut order = (0..triangles.len() as u32).collect::<Vec<_>>();
+        let nodes = if triangles.is_empty() {
+            Vec::new()
+        } else {
+            build_node(&mut order, 0, &input, opts)
+        };
+        Bvh { nodes, order, triangles }
+    }
+
+    pub fn triangle(&self, i: u32) -> &[glm::Vec3; 3] {
+        &self.triangles[i as usize]
+    }
+
+    fn leaf_triangles(&self, n: &Node) -> &[u32] {
+        &self.order[n.offset as usize..(n.offset + n.count) as usize]
+    }
+
+    /// Nearest triangle hit along the ray
+    pub fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<RayHit> {
+        let mut best: Option<RayHit> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() { stack.push(0) }
+        while let Some(i) = stack.pop() {
+            let n = &self.nodes[i];
+            let max = best.map_or(max_distance, |b| b.distance);
+            if ray_aabb(ray, &n.min, &n.max, max).is_none() { continue }
+            if n.count > 0 {
+                for &f in self.leaf_triangles(n) {
+                    let t = &self.triangles[f as usize];
+                    let max = best.map_or(max_distance, |b| b.distance);
+                    if let Some(d) = ray_triangle(ray, t, max) {
+                        best = Some(RayHit {
+                            point: ray.at(d),
+                            normal: face_normal(t),
+                            distance: d,
+                            id: HitId::Triangle(f),
+                        });
+                    }
+                }
+                continue
+            }
+            // Visit the nearer child first
+            let (a, b) = (i + 1, n.offset as usize);
+            let enter = |c: usize| ray_aabb(ray, &self.nodes[c].min, &self.nodes[c].max, max).map(|t| t.0);
+            let (first, second) = match (enter(a), enter(b)) {
+                (Some(ta), Some(tb)) if tb < ta => (b, a),
+                _ => (a, b),
+            };
+            stack.push(second);
+            stack.push(first);
+        }
+        best
+    }
+
+    /// Every triangle hit along the ray as (distance, triangle), unsorted
+    pub fn raycast_all(&self, ray: &Ray, max_distance: f32) -> Vec<(f32, u32)> {
+        let mut hits = Vec::new();
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() { stack.push(0) }
+        while let Some(i) = stack.pop() {
+            let n = &self.nodes[i];
+            if ray_aabb(ray, &n.min, &n.max, max_distance).is_none() { continue }
+            if n.count > 0 {
+                hits.extend(self.leaf_triangles(n).iter().filter_map(|&f| {
+                    ray_triangle(ray, &self.triangles[f as usize], max_distance).map(|d| (d, f))
+                }));
+                continue
+            }
+            stack.push(n.offset as usize);
+            stack.push(i + 1);
+        }
+        hits
+    }
+
+    /// Closest point on any triangle within `max_distance` of `p`
+    pub fn closest_point(&self, p: &glm::Vec3, max_distance: f32) -> Option<ClosestPoint> {
+        let mut best: Option<ClosestPoint> = None;
+        let mut best_d2 = max_distance * max_distance;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() { stack.push(0) }
+        while let Some(i) = stack.pop() {
+            let n = &self.nodes[i];
+            if aabb_distance2(p, &n.min, &n.max) > best_d2 { continue }
+            if n.count > 0 {
+                for &f in self.leaf_triangles(n) {
+                    let q = closest_point_on_triangle(p, &self.triangles[f as usize]);
+                    let d2 = glm::distance2(p, &q);
+                    if d2 <= best_d2 {
+                        best_d2 = d2;
+                        best = Some(ClosestPoint { point: q, distance: d2.sqrt(), triangle: f });
+                    }
+                }
+                continue
+            }
+            let (a, b) = (i + 1, n.offset as usize);
+            let d = |c: usize| aabb_distance2(p, &self.nodes[c].min, &self.nodes[c].max);
+            if d(a) < d(b) {
+                stack.push(b);
+                stack.push(a);
+            } else {
+                stack.push(a);
+                stack.push(b);
+            }
+        }
+        best
+    }
+
+    /// Triangles intersecting the box, in no particular order
+    pub fn overlap_aabb(&self, min: &glm::Vec3, max: &glm::Vec3) -> Vec<u32> {
+        let mut found = Vec::new();
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() { stack.push(0) }
+        while let Some(i) = stack.pop() {
+            let n = &self.nodes[i];
+            let overlaps = (0..3).all(|c| n.min[c] <= max[c] && n.max[c] >= min[c]);
+            if !overlaps { continue }
+            if n.count > 0 {
+                found.extend(self.leaf_triangles(n).iter()
+                    .filter(|&&f| triangle_overlaps_aabb(&self.triangles[f as usize], min, max)));
+                continue
+            }
+            stack.push(n.offset as usize);
+            stack.push(i + 1);
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    /// Sphere with a torus through it, over the parallel build threshold
+    fn scene() -> Mesh {
+        let mut mesh = Mesh::icosphere(1.0, 4);
+        let mut torus = Mesh::torus(1.2, 0.3, 24, 8);
+        torus.transform(&glm::rotation(0.7, &glm::Vec3::x()));
+        mesh.append(&torus);
+        assert!(mesh.indices.len() / 3 > PARALLEL_THRESHOLD);
+        mesh
+    }
+
+    fn triangles(mesh: &Mesh) -> Vec<[glm::Vec3; 3]> {
+        mesh.indices.chunks_exact(3)
+            .map(|t| [mesh.position(t[0]), mesh.position(t[1]), mesh.position(t[2])])
+            .collect()
+    }
+
+    fn random_point(rng: &mut impl Rng, extent: f32) -> glm::Vec3 {
+        glm::vec3(rng.gen_range(-extent..extent), rng.gen_range(-extent..extent), rng.gen_range(-extent..extent))
+    }
+
+    fn sorted(mut v: Vec<u32>) -> Vec<u32> {
+        v.sort_unstable();
+        v
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mesh = scene();
+        let tris = triangles(&mesh);
+        for build in [BvhBuild::Sweep, BvhBuild::Binned(16)] {
+            let bvh = Bvh::build(&mesh, &BvhOptions { build, ..Default::default() });
+            assert_eq!(sorted(bvh.order.clone()), (0..tris.len() as u32).collect::<Vec<_>>());
+            let mut rng = rand::rngs::StdRng::seed_from_u64(35);
+
+            for _ in 0..25 {
+                let origin = random_point(&mut rng, 3.0);
+                let ray = Ray::new(origin, glm::normalize(&(random_point(&mut rng, 1.0) - origin)));
+                let (hit, expected) = (bvh.raycast(&ray, 10.0), mesh.raycast(&ray, 10.0));
+                assert_eq!(hit.is_some(), expected.is_some(), "{build:?}");
+                if let (Some(hit), Some(expected)) = (hit, expected) {
+                    assert!((hit.distance - expected.distance).abs() < 1e-5, "{build:?}");
+                }
+
+                let all = bvh.raycast_all(&ray, 10.0).into_iter().map(|(_, f)| f).collect();
+                let expected = (0..tris.len() as u32).filter(|&f| ray_triangle(&ray, &tris[f as usize], 10.0).is_some());
+                assert_eq!(sorted(all), expected.collect::<Vec<_>>(), "{build:?}");
+            }
+
+            for _ in 0..25 {
+                let p = random_point(&mut rng, 2.0);
+                let expected = tris.iter()
+                    .map(|t| glm::distance(&p, &closest_point_on_triangle(&p, t)))
+                    .fold(f32::INFINITY, f32::min);
+                let closest = bvh.closest_point(&p, 10.0).unwrap();
+                assert!((closest.distance - expected).abs() < 1e-5, "{build:?}");
+                let t = &tris[closest.triangle as usize];
+                assert!(glm::distance(&closest.point, &closest_point_on_triangle(&p, t)) < 1e-5);
+                // Nothing within a radius short of the nearest triangle
+                assert!(bvh.closest_point(&p, expected * 0.99).is_none() || expected == 0.0);
+            }
+
+            for _ in 0..25 {
+                let (a, b) = (random_point(&mut rng, 1.5), random_point(&mut rng, 1.5));
+                let (min, max) = (glm::min2(&a, &b), glm::max2(&a, &b));
+                let expected = (0..tris.len() as u32).filter(|&f| triangle_overlaps_aabb(&tris[f as usize], &min, &max));
+                assert_eq!(sorted(bvh.overlap_aabb(&min, &max)), expected.collect::<Vec<_>>(), "{build:?}");
+            }
+        }
+    }
+}
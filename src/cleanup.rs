@@ -0,0 +1,164 @@
+// Removal of degenerate triangles, during or after extraction
+
+use crate::mc::Mesh;
+use crate::sink::{Triangle, TriangleSink};
+
+/// Why a triangle was considered degenerate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Degeneracy {
+    /// Two or more vertices coincide
+    Collapsed,
+    /// Distinct vertices, but area at or below the threshold
+    ZeroArea,
+}
+
+/// Classify a triangle, `None` if it should be kept
+pub fn degeneracy(v: &[glm::Vec3; 3], min_area: f32) -> Option<Degeneracy> {
+    if v[0] == v[1] || v[1] == v[2] || v[2] == v[0] {
+        return Some(Degeneracy::Collapsed)
+    }
+    let area = glm::length(&glm::cross(&(v[1] - v[0]), &(v[2] - v[0]))) * 0.5;
+    if area <= min_area || area.is_nan() {
+        return Some(Degeneracy::ZeroArea)
+    }
+    None
+}
+
+/// Counts reported by degenerate triangle removal
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DegenerateStats {
+    pub kept: usize,
+    pub collapsed: usize,
+    pub zero_area: usize,
+}
+impl DegenerateStats {
+    fn count(&mut self, d: Option<Degeneracy>) {
+        match d {
+            None => self.kept += 1,
+            Some(Degeneracy::Collapsed) => self.collapsed += 1,
+            Some(Degeneracy::ZeroArea) => self.zero_area += 1,
+        }
+    }
+    #[allow(unused)]
+    pub fn removed(&self) -> usize {
+        self.collapsed + self.zero_area
+    }
+}
+
+/// Sink adapter dropping degenerate triangles before they reach `inner`
+#[allow(unused)]
+pub struct DegenerateFilter<S: TriangleSink> {
+    pub inner: S,
+    pub min_area: f32,
+    pub stats: DegenerateStats,
+}
+#[allow(unused)]
+impl<S: TriangleSink> DegenerateFilter<S> {
+    pub fn new(inner: S, min_area: f32) -> Self {
+        DegenerateFilter { inner, min_area, stats: Default::default() }
+    }
+}
+impl<S: TriangleSink> TriangleSink for DegenerateFilter<S> {
+    fn triangle(&mut self, t: &Triangle) {
+        let d = degeneracy(&t.vertices, self.min_area);
+        self.stats.count(d);
+        if d.is_none() {
+            self.inner.triangle(t);
+        }
+    }
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Remove degenerate triangles, then any vertices no longer referenced
+    pub fn remove_degenerate(&mut self, min_area: f32) -> DegenerateStats {
+        let mut stats = DegenerateStats::default();
+        let mut indices = Vec::with_capacity(self.indices.len());
+        for t in self.indices.chunks_exact(3) {
+            let v = [self.position(t[0]), self.position(t[1]), self.position(t[2])];
+            let d = degeneracy(&v, min_area);
+            stats.count(d);
+            if d.is_none() {
+                indices.extend_from_slice(t);
+            }
+        }
+        self.indices = indices;
+        self.index_count = self.indices.len() as i32;
+        self.compact();
+        stats
+    }
+
+    /// Drop vertices not referenced by any triangle, along with their
+    /// normals, texture coordinates and colours. The rest are numbered in
+    /// order of first use. An attribute whose length is not a multiple of
+    /// the vertex count cannot be remapped, so it is cleared rather than
+    /// left out of step; `validate_attributes` reports such attributes
+    pub fn compact(&mut self) {
+        let n = self.vertex_count();
+        let mut remap = vec![u32::MAX; n];
+        let mut kept = Vec::new();
+        for i in self.indices.iter_mut() {
+            if remap[*i as usize] == u32::MAX {
+                remap[*i as usize] = kept.len() as u32;
+                kept.push(*i as usize);
+            }
+            *i = remap[*i as usize];
+        }
+        for attr in [
+            &mut self.vertices,
+            &mut self.normals,
+            &mut self.texture_coordinates,
+            &mut self.colors,
+            &mut self.occlusion,
+        ] {
+            // Attributes are per vertex, with as many components as fit
+            if n == 0 { continue }
+            if !attr.len().is_multiple_of(n) {
+                attr.clear();
+                continue;
+            }
+            let k = attr.len() / n;
+            *attr = kept.iter()
+                .flat_map(|&v| attr[v*k..v*k+k].to_vec())
+                .collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two triangles sharing an edge, plus an unused vertex 2
+    fn quad() -> Mesh {
+        Mesh {
+            vertices: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 9.0, 9.0, 9.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+            occlusion: vec![0.1, 0.2, 0.3, 0.4, 0.5],
+            indices: vec![0, 1, 3, 0, 3, 4],
+            index_count: 6,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn compact_drops_unused_vertices() {
+        let mut mesh = quad();
+        mesh.colors = [0.0, 1.0, 2.0, 3.0, 4.0].iter().flat_map(|&c| [c; 4]).collect();
+        mesh.compact();
+        assert_eq!(mesh.vertex_count(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.occlusion, vec![0.1, 0.2, 0.4, 0.5]);
+        assert_eq!(mesh.colors.chunks(4).map(|c| c[0]).collect::<Vec<_>>(), vec![0.0, 1.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn compact_clears_mismatched_attributes() {
+        let mut mesh = quad();
+        mesh.texture_coordinates = vec![0.0; 7];
+        assert!(mesh.validate_attributes().is_err());
+        mesh.compact();
+        assert!(mesh.texture_coordinates.is_empty());
+        assert_eq!(mesh.occlusion, vec![0.1, 0.2, 0.4, 0.5]);
+        assert_eq!(mesh.validate_attributes(), Ok(4));
+    }
+}
@@ -0,0 +1,79 @@
+// Per-vertex colours from colormapped scalar quantities
+
+use crate::colormap::Colormap;
+use crate::field::ScalarField;
+use crate::mc::Mesh;
+
+/// Floats per vertex written to `Mesh::colors`, RGBA
+pub const COLOR_STRIDE: usize = 4;
+
+/// Per-vertex quantity to colour by
+#[allow(unused)]
+#[derive(Clone, Copy)]
+pub enum ColorSource<'a> {
+    /// Position along `up`
+    Height { up: glm::Vec3 },
+    /// Angle in degrees between the normal and `up`: 0 on flat ground, 90
+    /// on vertical walls and up to 180 under overhangs
+    Slope { up: glm::Vec3 },
+    /// Mean curvature, positive where convex. The mesh must be welded
+    Curvature,
+    /// Gaussian curvature, negative at saddles. The mesh must be welded
+    GaussianCurvature,
+    /// An auxiliary field sampled at the vertex positions
+    Field(&'a dyn ScalarField),
+}
+
+#[derive(Clone, Copy)]
+pub struct ColorOptions<'a> {
+    pub source: ColorSource<'a>,
+    pub colormap: Colormap,
+    /// Values mapped onto the ends of the colormap. Defaults to the range
+    /// of the values, made symmetric around zero for diverging colormaps
+    pub range: Option<(f64, f64)>,
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// The quantity `source` at every vertex
+    pub fn vertex_scalars(&self, source: ColorSource) -> Vec<f64> {
+        let n = self.vertex_count() as u32;
+        match source {
+            ColorSource::Height { up } => {
+                let up = glm::normalize(&up);
+                (0..n).map(|i| glm::dot(&self.position(i), &up) as f64).collect()
+            },
+            ColorSource::Slope { up } => {
+                let up = glm::normalize(&up);
+                self.vertex_normals().iter()
+                    .map(|n| (glm::dot(n, &up).clamp(-1.0, 1.0) as f64).acos().to_degrees())
+                    .collect()
+            },
+            ColorSource::Curvature => self.curvature().iter().map(|c| c.mean).collect(),
+            ColorSource::GaussianCurvature => self.curvature().iter().map(|c| c.gaussian).collect(),
+            ColorSource::Field(field) => (0..n).map(|i| field.value(self.position(i))).collect(),
+        }
+    }
+
+    /// Fill `colors` with the colormapped quantity, replacing any there.
+    /// Returns the range mapped onto the colormap
+    pub fn colorize(&mut self, opts: &ColorOptions) -> (f64, f64) {
+        let values = self.vertex_scalars(opts.source);
+        let range = opts.range.unwrap_or_else(|| {
+            let finite = values.iter().copied().filter(|v| v.is_finite());
+            let (lo, hi) = finite.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)));
+            if lo > hi { return (0.0, 1.0) }
+            if opts.colormap.is_diverging() {
+                let m = lo.abs().max(hi.abs());
+                (-m, m)
+            } else {
+                (lo, hi)
+            }
+        });
+        self.colors = values.iter().flat_map(|&v| {
+            let c = opts.colormap.map(v, range);
+            [c.x, c.y, c.z, 1.0]
+        }).collect();
+        range
+    }
+}
@@ -0,0 +1,85 @@
+// Colormaps from scalar values to colours
+
+/// Maps t in [0, 1] to an RGB colour
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Colormap {
+    Grayscale,
+    /// Perceptually uniform, dark blue to yellow
+    #[default]
+    Viridis,
+    /// Water, lowlands, hills, rock and snow, as matplotlib's terrain
+    Terrain,
+    /// Blue through light grey to red, for values around a midpoint, as
+    /// Moreland's cool to warm
+    Diverging,
+}
+
+/// Polynomial fit of matplotlib's viridis, by Matt Zucker
+const VIRIDIS: [[f32; 3]; 7] = [
+    [0.277_727_33, 0.005_407_344_5, 0.334_099_8],
+    [0.105_093_04, 1.404_613_5, 1.384_590_2],
+    [-0.330_861_83, 0.214_847_56, 0.095_095_16],
+    [-4.634_230_6, -5.799_101, -19.332_441],
+    [6.228_27, 14.179_933, 56.690_55],
+    [4.776_385, -13.745_145, -65.353_03],
+    [-5.435_456, 4.645_852_6, 26.312_435],
+];
+
+const TERRAIN: [(f32, [f32; 3]); 6] = [
+    (0.0, [0.2, 0.2, 0.6]),
+    (0.15, [0.0, 0.6, 1.0]),
+    (0.25, [0.0, 0.8, 0.4]),
+    (0.5, [1.0, 1.0, 0.6]),
+    (0.75, [0.5, 0.36, 0.33]),
+    (1.0, [1.0, 1.0, 1.0]),
+];
+
+const DIVERGING: [(f32, [f32; 3]); 3] = [
+    (0.0, [0.230, 0.299, 0.754]),
+    (0.5, [0.865, 0.865, 0.865]),
+    (1.0, [0.706, 0.016, 0.150]),
+];
+
+/// Linear interpolation between colour stops sorted by position
+fn piecewise(stops: &[(f32, [f32; 3])], t: f32) -> glm::Vec3 {
+    let i = stops.iter().position(|s| s.0 >= t).unwrap_or(stops.len() - 1).max(1);
+    let ((t0, c0), (t1, c1)) = (stops[i - 1], stops[i]);
+    let s = ((t - t0) / (t1 - t0)).clamp(0.0, 1.0);
+    glm::make_vec3(&c0) + (glm::make_vec3(&c1) - glm::make_vec3(&c0)) * s
+}
+
+fn polynomial(c: &[[f32; 3]], t: f32) -> glm::Vec3 {
+    c.iter().rev().fold(glm::Vec3::zeros(), |acc, k| acc * t + glm::make_vec3(k))
+}
+
+#[allow(unused)]
+impl Colormap {
+    pub fn color(&self, t: f32) -> glm::Vec3 {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let c = match self {
+            Colormap::Grayscale => glm::Vec3::repeat(t),
+            Colormap::Viridis => polynomial(&VIRIDIS, t),
+            Colormap::Terrain => piecewise(&TERRAIN, t),
+            Colormap::Diverging => piecewise(&DIVERGING, t),
+        };
+        c.map(|x| x.clamp(0.0, 1.0))
+    }
+
+    /// Whether the colormap is meant for ranges centred on zero
+    pub fn is_diverging(&self) -> bool {
+        *self == Colormap::Diverging
+    }
+
+    /// Colour of `value` with `range` mapped onto the whole colormap
+    pub fn map(&self, value: f64, range: (f64, f64)) -> glm::Vec3 {
+        let width = range.1 - range.0;
+        let t = if width > 0.0 { (value - range.0) / width } else { 0.5 };
+        self.color(t as f32)
+    }
+
+    pub fn rgb8(&self, value: f64, range: (f64, f64)) -> [u8; 3] {
+        let c = self.map(value, range);
+        [c.x, c.y, c.z].map(|x| (x * 255.0).round() as u8)
+    }
+}
@@ -0,0 +1,188 @@
+// Connected component labelling, splitting and filtering
+
+use crate::mc::Mesh;
+use crate::measure::MassProperties;
+
+/// One connected piece of a mesh
+#[allow(unused)]
+#[derive(Clone, Debug)]
+pub struct Component {
+    /// Indices of the triangles in this component
+    pub triangles: Vec<u32>,
+    /// Area, volume, bounds etc. of the component alone
+    pub properties: MassProperties,
+}
+
+/// Size measure used to drop small components
+#[allow(unused)]
+#[derive(Clone, Copy, Debug)]
+pub enum ComponentSize {
+    Triangles(usize),
+    Area(f64),
+    /// Absolute enclosed volume. Fragments cut open by a chunk boundary
+    /// have no well defined volume, so prefer `Area` for chunk meshes
+    Volume(f64),
+    /// Length of the bounding box diagonal
+    Extent(f32),
+}
+impl ComponentSize {
+    fn reached_by(&self, c: &Component) -> bool {
+        match *self {
+            ComponentSize::Triangles(n) => c.triangles.len() >= n,
+            ComponentSize::Area(a) => c.properties.area >= a,
+            ComponentSize::Volume(v) => c.properties.volume.abs() >= v,
+            ComponentSize::Extent(e) => c.properties.bounds
+                .is_some_and(|(min, max)| glm::distance(&min, &max) >= e),
+        }
+    }
+}
+
+fn find(parent: &mut [u32], mut v: u32) -> u32 {
+    while parent[v as usize] != v {
+        parent[v as usize] = parent[parent[v as usize] as usize];
+        v = parent[v as usize];
+    }
+    v
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Component label of every triangle, numbered from 0 in order of first
+    /// appearance, and the number of components. Triangles are connected
+    /// when they share a vertex, so the mesh should be welded first
+    pub fn component_labels(&self) -> (Vec<u32>, usize) {
+        let mut parent = (0..self.vertex_count() as u32).collect::<Vec<_>>();
+        for t in self.indices.chunks_exact(3) {
+            let a = find(&mut parent, t[0]);
+            for &v in &t[1..] {
+                let b = find(&mut parent, v);
+                parent[b as usize] = a;
+            }
+        }
+        let mut label = vec![u32::MAX; self.vertex_count()];
+        let mut count = 0;
+        let labels = self.indices.chunks_exact(3).map(|t| {
+            let root = find(&mut parent, t[0]) as usize;
+            if label[root] == u32::MAX {
+                label[root] = count;
+                count += 1;
+            }
+            label[root]
+        }).collect();
+        (labels, count as usize)
+    }
+
+    /// Connected components, with per-component measurements
+    pub fn components(&self) -> Vec<Component> {
+        let (labels, count) = self.component_labels();
+        let mut components = vec![Component {
+            triangles: Vec::new(),
+            properties: MassProperties::new(),
+        }; count];
+        for (f, t) in self.indices.chunks_exact(3).enumerate() {
+            let c = &mut components[labels[f] as usize];
+            c.triangles.push(f as u32);
+            c.properties.add_triangle(&[self.position(t[0]), self.position(t[1]), self.position(t[2])]);
+        }
+        components
+    }
+
+    /// Mesh made of the given triangles only, with unused vertices dropped
+    pub fn select_triangles(&self, triangles: &[u32]) -> Mesh {
+        let indices = triangles.iter()
+            .flat_map(|&f| self.indices[f as usize * 3..f as usize * 3 + 3].to_vec())
+            .collect::<Vec<_>>();
+        let mut mesh = Mesh {
+            vertices: self.vertices.clone(),
+            normals: self.normals.clone(),
+            texture_coordinates: self.texture_coordinates.clone(),
+            colors: self.colors.clone(),
+            occlusion: self.occlusion.clone(),
+            index_count: indices.len() as i32,
+            indices,
+        };
+        mesh.compact();
+        mesh
+    }
+
+    /// One mesh per connected component
+    pub fn split_components(&self) -> Vec<Mesh> {
+        self.components().iter().map(|c| self.select_triangles(&c.triangles)).collect()
+    }
+
+    /// Drop components smaller than `min`, e.g. floating noise fragments.
+    /// Returns the number of components removed
+    pub fn remove_small_components(&mut self, min: ComponentSize) -> usize {
+        let components = self.components();
+        let mut keep = components.iter()
+            .filter(|c| min.reached_by(c))
+            .flat_map(|c| c.triangles.iter().copied())
+            .collect::<Vec<_>>();
+        keep.sort_unstable();
+        let removed = components.iter().filter(|c| !min.reached_by(c)).count();
+        *self = self.select_triangles(&keep);
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two disjoint spheres and a small fragment, welded, with 320, 80 and
+    /// 20 triangles in that order
+    fn scene() -> Mesh {
+        let mut mesh = Mesh::icosphere(1.0, 2);
+        for (radius, subdivisions, offset) in [(1.0, 1, glm::vec3(4.0, 0.0, 0.0)), (0.1, 0, glm::vec3(0.0, 4.0, 0.0))] {
+            let mut sphere = Mesh::icosphere(radius, subdivisions);
+            sphere.transform(&glm::translation(&offset));
+            mesh.append(&sphere);
+        }
+        mesh.weld()
+    }
+
+    #[test]
+    fn labels_follow_first_appearance() {
+        let (labels, count) = scene().component_labels();
+        assert_eq!(count, 3);
+        let expected = [(0, 320), (1, 80), (2, 20)].iter()
+            .flat_map(|&(label, n)| std::iter::repeat_n(label, n))
+            .collect::<Vec<u32>>();
+        assert_eq!(labels, expected);
+    }
+
+    #[test]
+    fn split_components_gives_closed_pieces() {
+        let mesh = scene();
+        let pieces = mesh.split_components();
+        assert_eq!(pieces.iter().map(|p| p.indices.len() / 3).collect::<Vec<_>>(), [320, 80, 20]);
+        for piece in &pieces {
+            assert!(piece.validate().is_watertight());
+            assert_eq!(piece.component_labels().1, 1);
+        }
+        let volume = pieces.iter().map(|p| p.volume()).sum::<f64>();
+        assert!((volume - mesh.volume()).abs() < 1e-9);
+        assert_eq!(pieces.iter().map(|p| p.vertex_count()).sum::<usize>(), mesh.vertex_count());
+    }
+
+    #[test]
+    fn remove_small_components_drops_only_the_fragment() {
+        let sizes = [
+            ComponentSize::Triangles(50),
+            ComponentSize::Area(1.0),
+            ComponentSize::Volume(0.1),
+            ComponentSize::Extent(1.0),
+        ];
+        for min in sizes {
+            let mut mesh = scene();
+            assert_eq!(mesh.remove_small_components(min), 1, "{min:?}");
+            assert_eq!(mesh.indices.len() / 3, 400, "{min:?}");
+            assert_eq!(mesh.component_labels().1, 2, "{min:?}");
+            assert!(mesh.validate().is_watertight(), "{min:?}");
+        }
+        // Nothing is removed below the smallest component
+        let mut mesh = scene();
+        assert_eq!(mesh.remove_small_components(ComponentSize::Triangles(20)), 0);
+        assert_eq!(mesh.indices.len() / 3, 420);
+    }
+}
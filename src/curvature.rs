@@ -0,0 +1,177 @@
+// Discrete curvature of meshes and fields
+
+use crate::field::ScalarField;
+use crate::mc::Mesh;
+
+/// Curvature at a point of a surface. Signs follow the outward normal, so a
+/// sphere of radius r has mean curvature 1/r
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Curvature {
+    pub mean: f64,
+    pub gaussian: f64,
+    /// Largest and smallest normal curvature
+    pub principal: [f64; 2],
+    /// Tangent directions of the principal curvatures
+    pub directions: [glm::Vec3; 2],
+}
+
+/// Orthonormal tangent basis of the plane with normal `n`
+fn tangent_basis(n: &glm::DVec3) -> (glm::DVec3, glm::DVec3) {
+    let a = if n.x.abs() < 0.9 { glm::DVec3::x() } else { glm::DVec3::y() };
+    let u = glm::normalize(&(a - n * glm::dot(&a, n)));
+    (u, glm::cross(n, &u))
+}
+
+impl Curvature {
+    /// From the second fundamental form `[[a, b], [b, c]]` in the tangent
+    /// basis (u, v). The mean and Gaussian curvature are taken as given, and
+    /// the form only decides the directions
+    fn from_form(mean: f64, gaussian: f64, (u, v): (glm::DVec3, glm::DVec3), [a, b, c]: [f64; 3]) -> Self {
+        let theta = 0.5 * (2.0 * b).atan2(a - c);
+        let d1 = u * theta.cos() + v * theta.sin();
+        let d2 = glm::cross(&glm::cross(&u, &v), &d1);
+        let disc = (mean * mean - gaussian).max(0.0).sqrt();
+        Curvature {
+            mean,
+            gaussian,
+            principal: [mean + disc, mean - disc],
+            directions: [glm::convert(d1), glm::convert(d2)],
+        }
+    }
+}
+
+/// Curvature of the isosurface of `field` through `p`, from the gradient
+/// and Hessian by central differences with step `h`. Zero where the
+/// gradient vanishes
+pub fn field_curvature<F: ScalarField + ?Sized>(field: &F, p: glm::Vec3, h: f32) -> Curvature {
+    let g = field.gradient(p, h);
+    let len = glm::length(&g);
+    if len == 0.0 || !len.is_finite() { return Curvature::default() }
+    let basis = tangent_basis(&(g / len));
+    let hess = field.hessian(p, h) / len;
+    let (u, v) = basis;
+    let form = [glm::dot(&u, &(hess * u)), glm::dot(&u, &(hess * v)), glm::dot(&v, &(hess * v))];
+    let mean = 0.5 * (form[0] + form[2]);
+    let gaussian = form[0] * form[2] - form[1] * form[1];
+    Curvature::from_form(mean, gaussian, basis, form)
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Curvature at every vertex. The mean curvature comes from the
+    /// cotangent Laplacian and the Gaussian from the angle deficit, both
+    /// over the mixed Voronoi area of Meyer et al. Principal directions come
+    /// from a least squares fit of the normal curvature along each edge.
+    /// The mesh must be welded, and boundary vertices are left at zero
+    pub fn curvature(&self) -> Vec<Curvature> {
+        let n = self.vertex_count();
+        let pos = (0..n as u32).map(|i| glm::convert::<_, glm::DVec3>(self.position(i))).collect::<Vec<_>>();
+        let mut laplacian = vec![glm::DVec3::zeros(); n];
+        let mut angles = vec![0.0; n];
+        let mut area = vec![0.0; n];
+
+        for t in self.indices.chunks_exact(3) {
+            let t = [t[0] as usize, t[1] as usize, t[2] as usize];
+            let p = t.map(|i| pos[i]);
+            let double_area = glm::length(&glm::cross(&(p[1] - p[0]), &(p[2] - p[0])));
+            if double_area == 0.0 { continue }
+            // Angle and its cotangent at each corner
+            let corner = [0, 1, 2].map(|i| {
+                let (u, v) = (p[(i + 1) % 3] - p[i], p[(i + 2) % 3] - p[i]);
+                let dot = glm::dot(&u, &v);
+                (double_area.atan2(dot), dot / double_area)
+            });
+            let obtuse = corner.iter().position(|c| c.0 > std::f64::consts::FRAC_PI_2);
+            for i in 0..3 {
+                let (j, k) = ((i + 1) % 3, (i + 2) % 3);
+                angles[t[i]] += corner[i].0;
+                // Edge (i, j) is opposite corner k
+                let w = 0.5 * corner[k].1;
+                laplacian[t[i]] += (p[j] - p[i]) * w;
+                laplacian[t[j]] += (p[i] - p[j]) * w;
+                area[t[i]] += match obtuse {
+                    None => 0.125 * (glm::distance2(&p[i], &p[j]) * corner[k].1 + glm::distance2(&p[i], &p[k]) * corner[j].1),
+                    Some(o) if o == i => 0.25 * double_area,
+                    Some(_) => 0.125 * double_area,
+                };
+            }
+        }
+
+        let boundary = self.boundary_vertices();
+        let normals = self.vertex_normals();
+        self.vertex_neighbours().iter().enumerate().map(|(i, ring)| {
+            if boundary[i] || area[i] == 0.0 || ring.len() < 3 { return Curvature::default() }
+            let normal: glm::DVec3 = glm::convert(normals[i]);
+            let mean = -glm::dot(&laplacian[i], &normal) / (2.0 * area[i]);
+            let gaussian = (2.0 * std::f64::consts::PI - angles[i]) / area[i];
+
+            // Fit a t_u² + 2b t_u t_v + c t_v² to the normal curvature along
+            // each edge, by the normal equations
+            let basis = tangent_basis(&normal);
+            let (mut ata, mut atb) = (glm::DMat3::zeros(), glm::DVec3::zeros());
+            for &j in ring {
+                let d = pos[j as usize] - pos[i];
+                let kappa = -2.0 * glm::dot(&normal, &d) / glm::length2(&d);
+                let t = d - normal * glm::dot(&normal, &d);
+                if glm::length2(&t) == 0.0 { continue }
+                let t = glm::normalize(&t);
+                let (x, y) = (glm::dot(&t, &basis.0), glm::dot(&t, &basis.1));
+                let row = glm::vec3(x * x, 2.0 * x * y, y * y);
+                ata += row * row.transpose();
+                atb += row * kappa;
+            }
+            let form = ata.try_inverse().map_or([mean, 0.0, mean], |inv| {
+                let f = inv * atb;
+                [f.x, f.y, f.z]
+            });
+            Curvature::from_form(mean, gaussian, basis, form)
+        }).collect()
+    }
+
+    /// Curvature of the field's isosurface at every vertex, e.g. the `Grid`
+    /// a mesh was just extracted from, with a step of one lattice spacing
+    pub fn field_curvature<F: ScalarField + ?Sized>(&self, field: &F, h: f32) -> Vec<Curvature> {
+        (0..self.vertex_count() as u32).map(|i| field_curvature(field, self.position(i), h)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn icosphere_has_sphere_curvature() {
+        let r = 2.0;
+        let mesh = Mesh::icosphere(r as f32, 3).weld();
+        let curvature = mesh.curvature();
+        assert_eq!(curvature.len(), mesh.vertex_count());
+        for c in &curvature {
+            assert!((c.mean * r - 1.0).abs() < 0.02, "mean {}", c.mean);
+            assert!((c.gaussian * r * r - 1.0).abs() < 0.05, "gaussian {}", c.gaussian);
+            assert!(c.principal[0] >= c.principal[1]);
+        }
+        // Gauss-Bonnet over the whole sphere
+        let area = mesh.area();
+        let mean_gaussian = curvature.iter().map(|c| c.gaussian).sum::<f64>() / curvature.len() as f64;
+        assert!((mean_gaussian * area / (4.0 * std::f64::consts::PI) - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn sphere_field_has_sphere_curvature() {
+        let r = 3.0;
+        let sdf = |p: glm::Vec3| glm::length(&p) as f64 - r;
+        for p in [glm::vec3(3.0, 0.0, 0.0), glm::vec3(0.0, -3.0, 0.0), glm::Vec3::repeat(3.0 / 3f32.sqrt())] {
+            let c = field_curvature(&sdf, p, 0.05);
+            assert!((c.mean * r - 1.0).abs() < 1e-2, "mean {} at {:?}", c.mean, p);
+            assert!((c.gaussian * r * r - 1.0).abs() < 2e-2, "gaussian {} at {:?}", c.gaussian, p);
+            for d in c.directions {
+                assert!(glm::dot(&d, &glm::normalize(&p)).abs() < 1e-3);
+            }
+        }
+        // Saddle z = x² - y² through the origin: mean 0 and Gaussian -4
+        let saddle = |p: glm::Vec3| (p.z - p.x * p.x + p.y * p.y) as f64;
+        let c = field_curvature(&saddle, glm::Vec3::zeros(), 0.01);
+        assert!(c.mean.abs() < 1e-2 && (c.gaussian + 4.0).abs() < 0.05, "{:?}", c);
+    }
+}
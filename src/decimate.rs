@@ -0,0 +1,373 @@
+// Mesh simplification by quadric error edge collapse (Garland and Heckbert,
+// "Surface Simplification Using Quadric Error Metrics", 1997)
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use crate::mc::Mesh;
+
+/// Weight of the planes added perpendicular to open boundary edges, which
+/// keep the boundary in place when it is not locked outright
+const BOUNDARY_WEIGHT: f64 = 1000.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct DecimateOptions {
+    /// Stop once the mesh has this many triangles or fewer
+    pub target_triangles: usize,
+    /// Never perform a collapse with a quadric error above this
+    pub max_error: f64,
+    /// Lock vertices on open boundary edges. Chunk meshes are open exactly
+    /// where they meet the neighbouring chunks, so this keeps the seams of
+    /// separately decimated chunks lined up
+    pub preserve_boundary: bool,
+}
+impl Default for DecimateOptions {
+    fn default() -> Self {
+        DecimateOptions {
+            target_triangles: 0,
+            max_error: f64::INFINITY,
+            preserve_boundary: true,
+        }
+    }
+}
+
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DecimateStats {
+    pub triangles_before: usize,
+    pub triangles_after: usize,
+    pub collapses: usize,
+    /// Largest quadric error of any collapse performed
+    pub max_error: f64,
+}
+
+/// Symmetric 4x4 matrix, upper triangle stored row by row
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+impl Quadric {
+    /// Squared distance to the plane n.x + d = 0, times `w`
+    fn plane(n: glm::DVec3, d: f64, w: f64) -> Self {
+        let (a, b, c) = (n.x, n.y, n.z);
+        Quadric([
+            a*a, a*b, a*c, a*d,
+                 b*b, b*c, b*d,
+                      c*c, c*d,
+                           d*d,
+        ].map(|q| q * w))
+    }
+    fn add(&self, o: &Quadric) -> Quadric {
+        let mut q = self.0;
+        q.iter_mut().zip(o.0.iter()).for_each(|(a, b)| *a += b);
+        Quadric(q)
+    }
+    fn error(&self, v: &glm::DVec3) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (v.x, v.y, v.z);
+        q[0]*x*x + 2.0*q[1]*x*y + 2.0*q[2]*x*z + 2.0*q[3]*x
+                 +     q[4]*y*y + 2.0*q[5]*y*z + 2.0*q[6]*y
+                                +     q[7]*z*z + 2.0*q[8]*z
+                                               +     q[9]
+    }
+    /// Position minimising the error, if well defined. The cut-off is
+    /// relative to the trace cubed, so it does not depend on the scale of
+    /// the mesh or the plane weights
+    fn minimum(&self) -> Option<glm::DVec3> {
+        let q = &self.0;
+        let a = glm::DMat3::new(
+            q[0], q[1], q[2],
+            q[1], q[4], q[5],
+            q[2], q[5], q[7],
+        );
+        if a.determinant().abs() <= 1e-9 * a.trace().powi(3) { return None }
+        a.try_inverse().map(|inv| -(inv * glm::vec3(q[3], q[6], q[8])))
+    }
+}
+
+/// Candidate collapse of edge (u, v), valid while both vertices are at the
+/// recorded versions
+struct Candidate {
+    cost: f64,
+    u: u32,
+    v: u32,
+    versions: (u32, u32),
+    target: glm::DVec3,
+}
+impl PartialEq for Candidate {
+    fn eq(&self, o: &Self) -> bool { self.cost == o.cost }
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, o: &Self) -> Option<Ordering> { Some(self.cmp(o)) }
+}
+impl Ord for Candidate {
+    // Reversed, so the max-heap pops the cheapest collapse
+    fn cmp(&self, o: &Self) -> Ordering { o.cost.total_cmp(&self.cost) }
+}
+
+struct Decimator {
+    pos: Vec<glm::DVec3>,
+    quadrics: Vec<Quadric>,
+    version: Vec<u32>,
+    removed: Vec<bool>,
+    locked: Vec<bool>,
+    faces: Vec<[u32; 3]>,
+    face_alive: Vec<bool>,
+    vertex_faces: Vec<Vec<u32>>,
+}
+impl Decimator {
+    fn face_normal(&self, f: &[u32; 3]) -> glm::DVec3 {
+        let (a, b, c) = (self.pos[f[0] as usize], self.pos[f[1] as usize], self.pos[f[2] as usize]);
+        glm::cross(&(b - a), &(c - a))
+    }
+
+    fn alive_faces(&self, v: u32) -> impl Iterator<Item = u32> + '_ {
+        self.vertex_faces[v as usize].iter().copied().filter(|&f| self.face_alive[f as usize])
+    }
+
+    fn neighbours(&self, v: u32) -> Vec<u32> {
+        let mut n = self.alive_faces(v)
+            .flat_map(|f| self.faces[f as usize])
+            .filter(|&w| w != v)
+            .collect::<Vec<_>>();
+        n.sort_unstable();
+        n.dedup();
+        n
+    }
+
+    fn candidate(&self, u: u32, v: u32) -> Option<Candidate> {
+        let (lu, lv) = (self.locked[u as usize], self.locked[v as usize]);
+        if lu && lv { return None }
+        let q = self.quadrics[u as usize].add(&self.quadrics[v as usize]);
+        let (pu, pv) = (self.pos[u as usize], self.pos[v as usize]);
+        let target = if lu { pu } else if lv { pv } else {
+            q.minimum().unwrap_or_else(|| {
+                [pu, pv, (pu + pv) * 0.5].into_iter()
+                    .min_by(|a, b| q.error(a).total_cmp(&q.error(b)))
+                    .unwrap()
+            })
+        };
+        Some(Candidate {
+            cost: q.error(&target).max(0.0),
+            u, v,
+            versions: (self.version[u as usize], self.version[v as usize]),
+            target,
+        })
+    }
+
+    /// Whether collapsing (u, v) to `target` keeps the mesh manifold and
+    /// does not flip any of the surrounding triangles
+    fn can_collapse(&self, u: u32, v: u32, target: &glm::DVec3) -> bool {
+        let shared = self.alive_faces(u)
+            .filter(|&f| self.faces[f as usize].contains(&v))
+            .count();
+        let nu = self.neighbours(u);
+        let common = self.neighbours(v).iter().filter(|w| nu.binary_search(w).is_ok()).count();
+        if common != shared { return false }
+
+        for w in [u, v] {
+            for f in self.alive_faces(w) {
+                let face = self.faces[f as usize];
+                if face.contains(&u) && face.contains(&v) { continue }
+                let before = self.face_normal(&face);
+                let (a, b, c) = (
+                    if face[0] == w { *target } else { self.pos[face[0] as usize] },
+                    if face[1] == w { *target } else { self.pos[face[1] as usize] },
+                    if face[2] == w { *target } else { self.pos[face[2] as usize] },
+                );
+                let after = glm::cross(&(b - a), &(c - a));
+                if glm::dot(&before, &after) <= 0.0 { return false }
+            }
+        }
+        true
+    }
+
+    /// Collapse v into u, returning the number of triangles removed
+    fn collapse(&mut self, u: u32, v: u32, target: glm::DVec3) -> usize {
+        let mut removed = 0;
+        for f in self.vertex_faces[v as usize].clone() {
+            if !self.face_alive[f as usize] { continue }
+            let face = &mut self.faces[f as usize];
+            if face.contains(&u) {
+                self.face_alive[f as usize] = false;
+                removed += 1;
+            } else {
+                face.iter_mut().filter(|w| **w == v).for_each(|w| *w = u);
+                self.vertex_faces[u as usize].push(f);
+            }
+        }
+        self.vertex_faces[v as usize].clear();
+        self.pos[u as usize] = target;
+        self.quadrics[u as usize] = self.quadrics[u as usize].add(&self.quadrics[v as usize]);
+        self.removed[v as usize] = true;
+        // u may have moved onto a locked v
+        self.locked[u as usize] |= self.locked[v as usize];
+        self.version[u as usize] += 1;
+        self.version[v as usize] += 1;
+        removed
+    }
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Simplify a welded mesh by repeatedly collapsing the edge with the
+    /// lowest quadric error, until the target triangle count or error bound
+    /// is reached. Returns a new mesh with recomputed normals. Texture
+    /// coordinates, colours and occlusion are not carried through collapses,
+    /// so the result has none; regenerate them on it, e.g. with
+    /// `generate_texture_coordinates`, `colorize` or `bake_occlusion`
+    pub fn decimate(&self, opts: &DecimateOptions) -> (Mesh, DecimateStats) {
+        let n = self.vertex_count();
+        // Faces keep the numbering of `indices`, so `edge_faces` applies, with
+        // degenerate ones dead from the start
+        let faces = self.indices.chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect::<Vec<_>>();
+        let face_alive = faces.iter()
+            .map(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+            .collect::<Vec<_>>();
+        let mut d = Decimator {
+            pos: (0..n).map(|i| crate::util::vec3_f32_to_f64(&self.position(i as u32))).collect(),
+            quadrics: vec![Quadric::default(); n],
+            version: vec![0; n],
+            removed: vec![false; n],
+            locked: vec![false; n],
+            face_alive,
+            vertex_faces: vec![Vec::new(); n],
+            faces,
+        };
+
+        // Face quadrics, weighted by area
+        for (f, face) in d.faces.iter().enumerate() {
+            if !d.face_alive[f] { continue }
+            let n = d.face_normal(face);
+            let len = glm::length(&n);
+            for &v in face {
+                d.vertex_faces[v as usize].push(f as u32);
+            }
+            if len == 0.0 { continue }
+            let normal = n / len;
+            let q = Quadric::plane(normal, -glm::dot(&normal, &d.pos[face[0] as usize]), len * 0.5);
+            for &v in face {
+                d.quadrics[v as usize] = d.quadrics[v as usize].add(&q);
+            }
+        }
+        let mut edge_faces = self.edge_faces();
+        edge_faces.retain(|&(a, b), fs| {
+            fs.retain(|&f| d.face_alive[f as usize]);
+            a != b && !fs.is_empty()
+        });
+
+        // Open boundary edges are either locked, or held by constraint planes
+        for (&(a, b), fs) in edge_faces.iter() {
+            if fs.len() != 1 { continue }
+            if opts.preserve_boundary {
+                d.locked[a as usize] = true;
+                d.locked[b as usize] = true;
+                continue
+            }
+            let (pa, pb) = (d.pos[a as usize], d.pos[b as usize]);
+            let fnormal = d.face_normal(&d.faces[fs[0] as usize]);
+            let perp = glm::cross(&(pb - pa), &fnormal);
+            let len = glm::length(&perp);
+            if len == 0.0 { continue }
+            let perp = perp / len;
+            let q = Quadric::plane(perp, -glm::dot(&perp, &pa), BOUNDARY_WEIGHT * glm::distance2(&pa, &pb));
+            d.quadrics[a as usize] = d.quadrics[a as usize].add(&q);
+            d.quadrics[b as usize] = d.quadrics[b as usize].add(&q);
+        }
+
+        let mut heap = edge_faces.keys()
+            .filter_map(|&(a, b)| d.candidate(a, b))
+            .collect::<BinaryHeap<_>>();
+
+        let mut triangles = d.face_alive.iter().filter(|&&alive| alive).count();
+        let mut stats = DecimateStats {
+            triangles_before: triangles,
+            ..Default::default()
+        };
+        while triangles > opts.target_triangles {
+            let c = match heap.pop() {
+                Some(c) => c,
+                None => break,
+            };
+            if c.cost > opts.max_error { break }
+            let (u, v) = (c.u, c.v);
+            if d.removed[u as usize] || d.removed[v as usize]
+                || c.versions != (d.version[u as usize], d.version[v as usize]) {
+                continue
+            }
+            if !d.can_collapse(u, v, &c.target) { continue }
+
+            triangles -= d.collapse(u, v, c.target);
+            stats.collapses += 1;
+            stats.max_error = stats.max_error.max(c.cost);
+            for w in d.neighbours(u) {
+                if let Some(c) = d.candidate(u, w) {
+                    heap.push(c);
+                }
+            }
+        }
+        stats.triangles_after = triangles;
+
+        let indices = d.faces.iter().zip(d.face_alive.iter())
+            .filter(|(_, &alive)| alive)
+            .flat_map(|(f, _)| *f)
+            .collect::<Vec<_>>();
+        let mut mesh = Mesh {
+            vertices: d.pos.iter().flat_map(|p| [p.x as f32, p.y as f32, p.z as f32]).collect(),
+            index_count: indices.len() as i32,
+            indices,
+            ..Default::default()
+        };
+        mesh.compact();
+        mesh.recompute_normals();
+        (mesh, stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimated_sphere_stays_closed() {
+        let sphere = Mesh::icosphere(5.0, 3).weld();
+        let opts = DecimateOptions { target_triangles: sphere.indices.len() / 3 / 4, ..Default::default() };
+        let (m, stats) = sphere.decimate(&opts);
+        assert_eq!(stats.triangles_before, sphere.indices.len() / 3);
+        assert_eq!(stats.triangles_after, m.indices.len() / 3);
+        assert!(stats.triangles_after <= opts.target_triangles);
+        let report = m.validate();
+        assert!(report.is_watertight(), "{:?}", report);
+        assert!(!sphere.texture_coordinates.is_empty() && m.texture_coordinates.is_empty());
+    }
+
+    #[test]
+    fn degenerate_triangles_are_skipped() {
+        let mut sphere = Mesh::icosphere(5.0, 2).weld();
+        let triangles = sphere.indices.len() / 3;
+        sphere.indices.extend_from_slice(&[0, 0, 1]);
+        sphere.index_count += 3;
+        let (_, stats) = sphere.decimate(&DecimateOptions { target_triangles: triangles, ..Default::default() });
+        assert_eq!(stats.triangles_before, triangles);
+        assert_eq!(stats.collapses, 0);
+    }
+
+    #[test]
+    fn minimum_is_independent_of_scale() {
+        let corner = glm::vec3(1.0, 2.0, 3.0);
+        for scale in [1e-4, 1.0, 1e4] {
+            let planes = |normals: &[glm::DVec3]| normals.iter().fold(Quadric::default(), |q, n| {
+                let n = glm::normalize(n);
+                q.add(&Quadric::plane(n, -glm::dot(&n, &(corner * scale)), scale * scale))
+            });
+            // Three orthogonal planes meet in a point
+            let q = planes(&[glm::DVec3::x(), glm::DVec3::y(), glm::DVec3::z()]);
+            let p = q.minimum().expect("corner");
+            assert!(glm::distance(&p, &(corner * scale)) < 1e-9 * scale);
+            // Nearly parallel planes do not
+            let q = planes(&[glm::DVec3::x(), glm::vec3(1.0, 1e-6, 0.0), glm::vec3(1.0, 0.0, 1e-6)]);
+            assert!(q.minimum().is_none());
+        }
+        assert!(Quadric::default().minimum().is_none());
+    }
+}
@@ -0,0 +1,111 @@
+// Scalar fields sampled at arbitrary world positions
+
+/// A scalar field over world space. As in marching cubes, values below the
+/// isolevel are inside the surface
+pub trait ScalarField {
+    fn value(&self, p: glm::Vec3) -> f64;
+
+    /// Gradient by central differences with step `h`. Points out of the
+    /// surface
+    fn gradient(&self, p: glm::Vec3, h: f32) -> glm::DVec3 {
+        let d = |axis: glm::Vec3| {
+            (self.value(p + axis * h) - self.value(p - axis * h)) / (2.0 * h as f64)
+        };
+        glm::vec3(d(glm::Vec3::x()), d(glm::Vec3::y()), d(glm::Vec3::z()))
+    }
+
+    /// Second derivatives by central differences with step `h`
+    fn hessian(&self, p: glm::Vec3, h: f32) -> glm::DMat3 {
+        let e = [glm::Vec3::x() * h, glm::Vec3::y() * h, glm::Vec3::z() * h];
+        let (f, h2) = (self.value(p), (h as f64).powi(2));
+        let mut m = glm::DMat3::zeros();
+        for i in 0..3 {
+            m[(i, i)] = (self.value(p + e[i]) - 2.0 * f + self.value(p - e[i])) / h2;
+            for j in 0..i {
+                let d = self.value(p + e[i] + e[j]) - self.value(p + e[i] - e[j])
+                    - self.value(p - e[i] + e[j]) + self.value(p - e[i] - e[j]);
+                m[(i, j)] = d / (4.0 * h2);
+                m[(j, i)] = m[(i, j)];
+            }
+        }
+        m
+    }
+}
+
+/// Analytic fields, e.g. signed distance functions
+impl<F: Fn(glm::Vec3) -> f64> ScalarField for F {
+    fn value(&self, p: glm::Vec3) -> f64 {
+        self(p)
+    }
+}
+
+/// The sampled volume used by `marching_cubes`, with lattice point (i,j,k)
+/// at world position (i,j,k) * scale, trilinearly interpolated in between.
+/// Positions outside the volume are clamped to its border
+#[derive(Clone, Copy)]
+pub struct Grid<'a> {
+    pub points: &'a [Vec<Vec<f64>>],
+    pub scale: f32,
+}
+
+#[allow(unused)]
+impl<'a> Grid<'a> {
+    pub fn new(points: &'a [Vec<Vec<f64>>], scale: f32) -> Self {
+        Grid { points, scale }
+    }
+
+    /// Number of lattice points along each axis
+    pub fn dims(&self) -> (usize, usize, usize) {
+        let nx = self.points.len();
+        let ny = self.points.first().map_or(0, |p| p.len());
+        let nz = self.points.first().and_then(|p| p.first()).map_or(0, |p| p.len());
+        (nx, ny, nz)
+    }
+
+    /// Lattice cell containing `p`, if inside the volume
+    pub fn cell(&self, p: glm::Vec3) -> Option<[usize; 3]> {
+        let (nx, ny, nz) = self.dims();
+        let q = p / self.scale;
+        let c = [q.x.floor(), q.y.floor(), q.z.floor()];
+        let inside = c.iter().zip([nx, ny, nz]).all(|(&c, n)| c >= 0.0 && (c as usize) + 1 < n);
+        inside.then(|| [c[0] as usize, c[1] as usize, c[2] as usize])
+    }
+}
+
+impl ScalarField for Grid<'_> {
+    fn value(&self, p: glm::Vec3) -> f64 {
+        let (nx, ny, nz) = self.dims();
+        if nx < 2 || ny < 2 || nz < 2 { return 0.0 }
+        let q = p / self.scale;
+        let split = |x: f32, n: usize| {
+            let x = x.clamp(0.0, (n - 1) as f32) as f64;
+            let i = (x.floor() as usize).min(n - 2);
+            (i, x - i as f64)
+        };
+        let ((i, fx), (j, fy), (k, fz)) = (split(q.x, nx), split(q.y, ny), split(q.z, nz));
+        let v = |di: usize, dj: usize, dk: usize| self.points[i+di][j+dj][k+dk];
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        lerp(
+            lerp(lerp(v(0,0,0), v(0,0,1), fz), lerp(v(0,1,0), v(0,1,1), fz), fy),
+            lerp(lerp(v(1,0,0), v(1,0,1), fz), lerp(v(1,1,0), v(1,1,1), fz), fy),
+            fx,
+        )
+    }
+}
+
+/// Sample a field on a planar lattice of `nu` by `nv` points, point (i, j)
+/// at `origin + i * u + j * v`. The result is indexed `[i][j]`, as
+/// `marching_squares` expects
+#[allow(unused)]
+pub fn sample_plane<F: ScalarField + ?Sized>(
+    field: &F,
+    origin: glm::Vec3,
+    u: glm::Vec3,
+    v: glm::Vec3,
+    nu: usize,
+    nv: usize,
+) -> Vec<Vec<f64>> {
+    (0..nu).map(|i| (0..nv).map(|j| {
+        field.value(origin + u * i as f32 + v * j as f32)
+    }).collect()).collect()
+}
@@ -0,0 +1,290 @@
+// Typed vertex attribute layouts and packed vertex buffers
+
+use std::fmt;
+use std::os::raw::c_void;
+use crate::mc::Mesh;
+use crate::colorize::COLOR_STRIDE;
+use crate::texcoord::TRIPLANAR_STRIDE;
+
+/// Shader locations of the attributes in `shaders/simple.vert`
+pub const POSITION_LOCATION: u32 = 0;
+pub const NORMAL_LOCATION: u32 = 1;
+/// Triplanar weights, followed by the three projections' UVs at the next
+/// three locations
+pub const TEXCOORD_LOCATION: u32 = 2;
+pub const COLOR_LOCATION: u32 = 6;
+pub const OCCLUSION_LOCATION: u32 = 7;
+/// Plain texture coordinates, of which the shader reads the first two
+pub const PLAIN_TEXCOORD_LOCATION: u32 = 8;
+
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComponentType {
+    F32,
+    I32,
+    U32,
+    I16,
+    U16,
+    I8,
+    U8,
+}
+
+#[allow(unused)]
+impl ComponentType {
+    /// Bytes per component
+    pub fn size(self) -> usize {
+        match self {
+            ComponentType::F32 | ComponentType::I32 | ComponentType::U32 => 4,
+            ComponentType::I16 | ComponentType::U16 => 2,
+            ComponentType::I8 | ComponentType::U8 => 1,
+        }
+    }
+
+    pub fn gl_type(self) -> gl::types::GLenum {
+        match self {
+            ComponentType::F32 => gl::FLOAT,
+            ComponentType::I32 => gl::INT,
+            ComponentType::U32 => gl::UNSIGNED_INT,
+            ComponentType::I16 => gl::SHORT,
+            ComponentType::U16 => gl::UNSIGNED_SHORT,
+            ComponentType::I8 => gl::BYTE,
+            ComponentType::U8 => gl::UNSIGNED_BYTE,
+        }
+    }
+}
+
+/// Name, location, component type, component count and whether integers
+/// are normalized, as described for `Attribute`
+pub type AttributeSpec = (&'static str, u32, ComponentType, usize, bool);
+
+/// One named vertex attribute and where it sits in the vertex buffer
+#[allow(unused)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attribute {
+    pub name: &'static str,
+    pub location: u32,
+    pub component_type: ComponentType,
+    /// 1 to 4
+    pub components: usize,
+    /// Integer components are mapped to [0, 1] or [-1, 1] rather than
+    /// converted to float as they are
+    pub normalized: bool,
+    /// Bytes from the start of the buffer to the attribute of vertex 0
+    pub offset: usize,
+    /// Bytes from one vertex's attribute to the next's
+    pub stride: usize,
+}
+
+#[allow(unused)]
+impl Attribute {
+    /// Bytes per vertex
+    pub fn size(&self) -> usize {
+        self.components * self.component_type.size()
+    }
+}
+
+/// Attributes of `vertex_count` vertices in one buffer, either interleaved,
+/// with each vertex's attributes together, or planar, with each attribute's
+/// values together
+#[allow(unused)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct VertexLayout {
+    pub attributes: Vec<Attribute>,
+    pub vertex_count: usize,
+    pub interleaved: bool,
+}
+
+#[allow(unused)]
+impl VertexLayout {
+    /// Lay out the attributes in the order given
+    pub fn new(attributes: &[AttributeSpec], vertex_count: usize, interleaved: bool) -> Self {
+        let vertex_size = attributes.iter().map(|&(_, _, t, c, _)| t.size() * c).sum::<usize>();
+        let mut offset = 0;
+        let attributes = attributes.iter().map(|&(name, location, component_type, components, normalized)| {
+            let size = component_type.size() * components;
+            let attribute = Attribute {
+                name, location, component_type, components, normalized,
+                offset,
+                stride: if interleaved { vertex_size } else { size },
+            };
+            offset += if interleaved { size } else { size * vertex_count };
+            attribute
+        }).collect();
+        VertexLayout { attributes, vertex_count, interleaved }
+    }
+
+    /// Bytes of all attributes of one vertex
+    pub fn vertex_size(&self) -> usize {
+        self.attributes.iter().map(Attribute::size).sum()
+    }
+
+    /// Bytes of the whole buffer
+    pub fn byte_size(&self) -> usize {
+        self.vertex_size() * self.vertex_count
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|a| a.name == name)
+    }
+
+    /// Enable and point every attribute at the buffer bound to
+    /// `GL_ARRAY_BUFFER`, recording them in the bound vertex array
+    pub unsafe fn apply(&self) {
+        for a in &self.attributes {
+            gl::EnableVertexAttribArray(a.location);
+            gl::VertexAttribPointer(
+                a.location,
+                a.components as i32,
+                a.component_type.gl_type(),
+                if a.normalized { gl::TRUE } else { gl::FALSE },
+                a.stride as i32,
+                a.offset as *const c_void,
+            );
+        }
+    }
+}
+
+/// Ways the fields of a `Mesh` can disagree with each other
+#[allow(unused)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayoutError {
+    /// `vertices` is not a whole number of 3D positions
+    Positions { len: usize },
+    /// An attribute has a number of values that is not one of the counts
+    /// per vertex it allows, times the vertex count
+    AttributeLength { name: &'static str, len: usize, vertex_count: usize },
+    /// `indices` is not a whole number of triangles
+    Indices { len: usize },
+    /// `index_count` disagrees with `indices`
+    IndexCount { index_count: i32, len: usize },
+    IndexOutOfRange { index: u32, vertex_count: usize },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayoutError::Positions { len } =>
+                write!(f, "{} position values is not a multiple of 3", len),
+            LayoutError::AttributeLength { name, len, vertex_count } =>
+                write!(f, "{} has {} values for {} vertices", name, len, vertex_count),
+            LayoutError::Indices { len } =>
+                write!(f, "{} indices is not a multiple of 3", len),
+            LayoutError::IndexCount { index_count, len } =>
+                write!(f, "index_count is {} but there are {} indices", index_count, len),
+            LayoutError::IndexOutOfRange { index, vertex_count } =>
+                write!(f, "index {} is out of range for {} vertices", index, vertex_count),
+        }
+    }
+}
+
+/// A run of `components` floats per vertex starting `first` floats into each
+/// vertex's `stride` floats of `data`
+struct Source<'a> {
+    data: &'a [f32],
+    stride: usize,
+    first: usize,
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Check that every attribute holds the same number of vertices, and
+    /// that the indices are whole triangles of existing vertices matching
+    /// `index_count`. Returns the vertex count. Empty attributes are absent
+    /// rather than mismatched
+    pub fn validate_attributes(&self) -> Result<usize, LayoutError> {
+        self.attribute_sources().map(|(list, _)| list.vertex_count)
+    }
+
+    /// Layout of the mesh's attributes at the locations of
+    /// `shaders/simple.vert`, all as `f32`, leaving out empty attributes
+    pub fn vertex_layout(&self, interleaved: bool) -> Result<VertexLayout, LayoutError> {
+        let (list, _) = self.attribute_sources()?;
+        Ok(VertexLayout::new(&list.attributes, list.vertex_count, interleaved))
+    }
+
+    /// The layout and a buffer of the attributes laid out by it, ready for
+    /// `glBufferData` followed by `VertexLayout::apply`
+    pub fn vertex_buffer(&self, interleaved: bool) -> Result<(VertexLayout, Vec<u8>), LayoutError> {
+        let (list, sources) = self.attribute_sources()?;
+        let layout = VertexLayout::new(&list.attributes, list.vertex_count, interleaved);
+        let mut bytes = vec![0; layout.byte_size()];
+        for (a, s) in layout.attributes.iter().zip(&sources) {
+            for v in 0..layout.vertex_count {
+                let values = &s.data[v * s.stride + s.first..][..a.components];
+                let at = a.offset + v * a.stride;
+                for (i, x) in values.iter().enumerate() {
+                    bytes[at + i * 4..at + i * 4 + 4].copy_from_slice(&x.to_ne_bytes());
+                }
+            }
+        }
+        Ok((layout, bytes))
+    }
+
+    /// Attribute descriptions without offsets, paired with where their
+    /// values come from, after validating the mesh
+    fn attribute_sources(&self) -> Result<(AttributeList, Vec<Source<'_>>), LayoutError> {
+        if !self.vertices.len().is_multiple_of(3) {
+            return Err(LayoutError::Positions { len: self.vertices.len() });
+        }
+        let n = self.vertex_count();
+        let mut entries = Vec::new();
+        entries.push(float_attribute("position", POSITION_LOCATION, 3, &self.vertices, 3, 0));
+
+        // Per-vertex counts each attribute allows when present
+        let per_vertex = |name, data: &[f32], allowed: &[usize]| match data.len() {
+            0 => Ok(None),
+            len if n > 0 && len.is_multiple_of(n) && allowed.contains(&(len / n)) => Ok(Some(len / n)),
+            len => Err(LayoutError::AttributeLength { name, len, vertex_count: n }),
+        };
+        if per_vertex("normals", &self.normals, &[3])?.is_some() {
+            entries.push(float_attribute("normal", NORMAL_LOCATION, 3, &self.normals, 3, 0));
+        }
+        match per_vertex("texture_coordinates", &self.texture_coordinates, &[1, 2, 3, 4, TRIPLANAR_STRIDE])? {
+            Some(TRIPLANAR_STRIDE) => {
+                let t = &self.texture_coordinates;
+                entries.push(float_attribute("tex_weights", TEXCOORD_LOCATION, 3, t, TRIPLANAR_STRIDE, 0));
+                entries.push(float_attribute("tex_uv_x", TEXCOORD_LOCATION + 1, 2, t, TRIPLANAR_STRIDE, 3));
+                entries.push(float_attribute("tex_uv_y", TEXCOORD_LOCATION + 2, 2, t, TRIPLANAR_STRIDE, 5));
+                entries.push(float_attribute("tex_uv_z", TEXCOORD_LOCATION + 3, 2, t, TRIPLANAR_STRIDE, 7));
+            }
+            Some(c) => entries.push(float_attribute("tex_coord", PLAIN_TEXCOORD_LOCATION, c, &self.texture_coordinates, c, 0)),
+            None => {}
+        }
+        if per_vertex("colors", &self.colors, &[COLOR_STRIDE])?.is_some() {
+            entries.push(float_attribute("color", COLOR_LOCATION, COLOR_STRIDE, &self.colors, COLOR_STRIDE, 0));
+        }
+        if per_vertex("occlusion", &self.occlusion, &[1])?.is_some() {
+            entries.push(float_attribute("occlusion", OCCLUSION_LOCATION, 1, &self.occlusion, 1, 0));
+        }
+
+        if !self.indices.len().is_multiple_of(3) {
+            return Err(LayoutError::Indices { len: self.indices.len() });
+        }
+        if self.index_count as usize != self.indices.len() || self.index_count < 0 {
+            return Err(LayoutError::IndexCount { index_count: self.index_count, len: self.indices.len() });
+        }
+        if let Some(&index) = self.indices.iter().find(|&&i| i as usize >= n) {
+            return Err(LayoutError::IndexOutOfRange { index, vertex_count: n });
+        }
+        let (attributes, sources) = entries.into_iter().unzip();
+        Ok((AttributeList { attributes, vertex_count: n }, sources))
+    }
+}
+
+/// A float attribute of `components` values read from `data` as by `Source`
+fn float_attribute<'a>(
+    name: &'static str,
+    location: u32,
+    components: usize,
+    data: &'a [f32],
+    stride: usize,
+    first: usize,
+) -> (AttributeSpec, Source<'a>) {
+    ((name, location, ComponentType::F32, components, false), Source { data, stride, first })
+}
+
+/// Attributes in the form taken by `VertexLayout::new`
+struct AttributeList {
+    attributes: Vec<AttributeSpec>,
+    vertex_count: usize,
+}
@@ -0,0 +1,53 @@
+// Line list geometry for wireframes and debug drawing
+
+/// Vertices joined by segments, drawn with `GL_LINES`. Each pair of
+/// `indices` is one segment
+#[allow(unused)]
+#[derive(Default)]
+pub struct Lines {
+    pub vertices: Vec<f32>,
+    /// RGBA per vertex, or empty
+    pub colors: Vec<f32>,
+    pub indices: Vec<u32>,
+}
+
+#[allow(unused)]
+impl Lines {
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len() / 3
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.indices.len() / 2
+    }
+
+    /// Position of vertex `i`
+    pub fn position(&self, i: u32) -> glm::Vec3 {
+        let i = i as usize * 3;
+        glm::vec3(self.vertices[i], self.vertices[i + 1], self.vertices[i + 2])
+    }
+
+    /// Edges of a block of `counts` boxes of `size` from `origin`, e.g. the
+    /// chunks handed to `marching_cubes`. Edges shared by neighbouring boxes
+    /// are drawn once, as one segment through the whole block
+    pub fn chunk_grid(counts: [usize; 3], size: glm::Vec3, origin: glm::Vec3) -> Lines {
+        let mut lines = Lines::default();
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            for i in 0..=counts[u] {
+                for j in 0..=counts[v] {
+                    let mut p = origin;
+                    p[u] += i as f32 * size[u];
+                    p[v] += j as f32 * size[v];
+                    let mut q = p;
+                    q[axis] += counts[axis] as f32 * size[axis];
+                    let first = lines.vertex_count() as u32;
+                    lines.vertices.extend_from_slice(p.as_slice());
+                    lines.vertices.extend_from_slice(q.as_slice());
+                    lines.indices.extend_from_slice(&[first, first + 1]);
+                }
+            }
+        }
+        lines
+    }
+}
@@ -6,18 +6,48 @@ use std::sync::{Mutex, Arc, RwLock};
 mod shader;
 mod util;
 mod mc;
-
-use glutin::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, ElementState::{Pressed, Released}, VirtualKeyCode::{self, *}};
+mod sink;
+mod cleanup;
+mod weld;
+mod decimate;
+mod topology;
+mod smooth;
+mod validate;
+mod measure;
+mod components;
+mod merge;
+mod field;
+mod ray;
+mod bvh;
+mod voxelize;
+mod octree;
+mod raster;
+mod squares;
+mod colormap;
+mod slice;
+mod texcoord;
+mod colorize;
+mod curvature;
+mod occlusion;
+mod lines;
+mod primitives;
+mod vcache;
+mod quantize;
+mod layout;
+mod volume;
+
+use glutin::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, MouseButton, ElementState::{Pressed, Released}, VirtualKeyCode::{self, *}};
 use glutin::event_loop::ControlFlow;
 
 const SCREEN_W: u32 = 800;
 const SCREEN_H: u32 = 600;
 const SEED: u32 = 219734390;
+const VOLUME_PATH: &str = "./points.vol";
 
 // == // Helper functions to make interacting with OpenGL a little bit prettier. You *WILL* need these! // == //
 // The names should be pretty self explanatory
 fn byte_size_of_array<T>(val: &[T]) -> isize {
-    std::mem::size_of_val(&val[..]) as isize
+    std::mem::size_of_val(val) as isize
 }
 
 // Get the OpenGL-compatible pointer to an arbitrary array of numbers
@@ -26,11 +56,13 @@ fn pointer_to_array<T>(val: &[T]) -> *const c_void {
 }
 
 // Get the size of the given type in bytes
+#[allow(unused)]
 fn size_of<T>() -> i32 {
     mem::size_of::<T>() as i32
 }
 
 // Get an offset in bytes for n units of type T
+#[allow(unused)]
 fn offset<T>(n: u32) -> *const c_void {
     (n * mem::size_of::<T>() as u32) as *const T as *const c_void
 }
@@ -44,24 +76,27 @@ fn offset<T>(n: u32) -> *const c_void {
 // unsafe fn FUNCTION_NAME(ARGUMENT_NAME: &Vec<f32>, ARGUMENT_NAME: &Vec<u32>) -> u32 { }
 
 fn main() {
-    if !std::path::Path::new("./points.txt").exists() {
-        eprint!("File points.txt not found. Generating point cloud . . . ");
-        use noise::{NoiseFn, Perlin};
-        use std::io::Write;
-        let perlin = Perlin::new(SEED);
-        let nfreq = 0.2;
-        let mut f = std::fs::File::create("./points.txt").unwrap();
-
-        writeln!(f, "129 129 129");
-        (0..129).for_each(|i|{
-            (0..129).for_each(|j|{
-                (0..129).for_each(|k|{
-                    write!(f, "{:.4} ", (perlin.get([i as f64 * nfreq, j as f64 * nfreq, k as f64 * nfreq]) + 1.0) / 2.0);}
-                );}
-            );}
-        );
+    if !std::path::Path::new(VOLUME_PATH).exists() {
+        let spacing = glm::Vec3::repeat(0.5);
+        if std::path::Path::new("./points.txt").exists() {
+            eprint!("Converting points.txt to {} . . . ", VOLUME_PATH);
+            volume::convert_legacy("./points.txt", VOLUME_PATH, spacing, glm::Vec3::zeros(), &Default::default()).unwrap();
+        } else {
+            eprint!("File {} not found. Generating point cloud . . . ", VOLUME_PATH);
+            use noise::{NoiseFn, Perlin};
+            let perlin = Perlin::new(SEED);
+            let nfreq = 0.2;
+            volume::SampledVolume::from_fn([129, 129, 129], spacing, glm::Vec3::zeros(), |i, j, k|
+                (perlin.get([i as f64 * nfreq, j as f64 * nfreq, k as f64 * nfreq]) + 1.0) / 2.0
+            ).save(VOLUME_PATH, &Default::default()).unwrap();
+        }
         eprintln!("done.")
     }
+    // Fail before opening a window on volumes the viewer cannot draw
+    let volume = volume::SampledVolume::load(VOLUME_PATH).unwrap();
+    let scale = volume.scale().unwrap();
+    let chunk_counts = volume.chunk_counts().unwrap();
+    eprintln!("Loaded {:?} points", volume.dims());
 
     // Set up the necessary objects to deal with windows and event handling
     let event_loop = glutin::event_loop::EventLoop::new();
@@ -86,6 +121,11 @@ fn main() {
     // Make a reference of this tuple to send to the render thread
     let mouse_delta = Arc::clone(&arc_mouse_delta);
 
+    // Set up shared cursor position in pixels, and whether a click is waiting to be picked
+    let arc_cursor = Arc::new(Mutex::new(((0f32, 0f32), false)));
+    // Make a reference of this to send to the render thread
+    let cursor = Arc::clone(&arc_cursor);
+
     // Spawn a separate thread for rendering, so event handling doesn't block rendering
     let render_thread = thread::spawn(move || {
         // Acquire the OpenGL Context and load the function pointers. This has to be done inside of the rendering thread, because
@@ -105,6 +145,8 @@ fn main() {
             gl::Disable(gl::MULTISAMPLE);
             gl::Enable(gl::BLEND);
             gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            // Meshes without baked occlusion are fully open
+            gl::VertexAttrib1f(layout::OCCLUSION_LOCATION, 1.0);
             gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
             gl::DebugMessageCallback(Some(util::debug_callback), ptr::null());
 
@@ -114,39 +156,39 @@ fn main() {
             eprintln!("GLSL\t: {}", util::get_gl_string(gl::SHADING_LANGUAGE_VERSION));
         }
 
-        use std::io::{prelude::*, BufReader};
-        use std::fs::File;
-        let f = File::open("./points.txt").unwrap();
-        let mut f = BufReader::new(f);
-        let mut bd = String::new();
-        let _ = f.read_line(&mut bd);
-        let bd = bd.trim().split_ascii_whitespace().map(|s| s.parse::<usize>().unwrap()).collect::<Vec<_>>();
-        let mut f = f.split(b' ');
-        let f = f.map(|b| std::str::from_utf8(&b.unwrap()).unwrap().parse::<f64>().unwrap()).collect::<Vec<_>>();
-        eprintln!("Loaded {}/{} points", f.len(), bd[0]*bd[1]*bd[2]);
-        println!("{} {} {} {}", f[0], f[1], f[2], f[3]);
-        let points = (0..bd[0]).map(|i|
-            (0..bd[1]).map(|j|
-                (0..bd[2]).map(|k|
-                    f[i*bd[1]*bd[2]+j*bd[1]+k]
-                    //std::str::from_utf8(&f.next().unwrap().unwrap()).unwrap().parse::<f64>().unwrap()
-                    //(perlin.get([i as f64 * nfreq, j as f64 * nfreq, k as f64 * nfreq]) + 1.0) / 2.0
-                ).collect::<Vec<_>>()
-            ).collect::<Vec<_>>()
-        ).collect::<Vec<_>>();
-
-        use rayon::prelude::*;
-        let s = 8;
-        let chunks = (0..s*s*s).map(|i|{
-            eprintln!("MC on chunk ({},{},{})",(i as usize/ (s*s))*16,((i as usize/s)%s)*16,(i as usize%s)*16);
-            mc::marching_cubes(((i as usize/ (s*s))*16,((i as usize/s)%s)*16,(i as usize%s)*16), 0.5, &points, 0.4)
+        // Meshes are built in lattice space, and placed at the volume's
+        // origin by the model matrix
+        let origin = volume.origin;
+        let chunk_size = volume::CHUNK as f32 * scale;
+        let extent = glm::vec3(chunk_counts[0] as f32, chunk_counts[1] as f32, chunk_counts[2] as f32) * chunk_size;
+        let chunk_origins = volume.chunk_origins().unwrap();
+        let points = volume.points;
+
+        let chunks = chunk_origins.into_iter().map(|c|{
+            eprintln!("MC on chunk {:?}", c);
+            let opts = mc::McOptions {
+                texture: mc::TexturePolicy::Triplanar { scale: 2.0, sharpness: 4.0 },
+                ..Default::default()
+            };
+            let mut m = mc::marching_cubes_with(c, scale, &points, 0.4, &opts);
+            // Fixed range so the colours agree across chunks
+            m.colorize(&colorize::ColorOptions {
+                source: colorize::ColorSource::Height { up: glm::Vec3::y() },
+                colormap: colormap::Colormap::Terrain,
+                range: Some((0.0, extent.y as f64)),
+            });
+            // Sampling the whole volume lets neighbouring chunks occlude
+            m.bake_occlusion(&occlusion::OcclusionOptions {
+                method: occlusion::OcclusionMethod::Field { field: &field::Grid::new(&points, scale), isolevel: 0.4, steps: 6 },
+                samples: 16,
+                radius: 3.0,
+            });
+            m
         }).collect::<Vec<_>>();
         let chunks = chunks.into_iter().map(|m| {
 
-            let vertices = m.vertices;
+            let (layout, vertex_data) = m.vertex_buffer(true).unwrap();
             let indices = m.indices;
-            let normals = m.normals;
-            let cube_ic = m.index_count;
 
             //---------------------------------------------------------------------/
             // Set up VAO
@@ -166,32 +208,18 @@ fn main() {
                     gl::STATIC_DRAW
                 );
 
+                // Every attribute interleaved in one buffer
                 let mut vbo = 0;
                 gl::GenBuffers(1, &mut vbo);
                 gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
                 gl::BufferData(
                     gl::ARRAY_BUFFER,
-                    byte_size_of_array(&vertices),
-                    pointer_to_array(&vertices) as *const _,
-                    gl::STATIC_DRAW
-                );
-
-                gl::EnableVertexAttribArray(0);
-                gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
-
-                let mut nbo = 0;
-                gl::GenBuffers(1, &mut nbo);
-                gl::BindBuffer(gl::ARRAY_BUFFER, nbo);
-                gl::BufferData(
-                    gl::ARRAY_BUFFER,
-                    byte_size_of_array(&normals),
-                    pointer_to_array(&normals) as *const _,
+                    byte_size_of_array(&vertex_data),
+                    pointer_to_array(&vertex_data) as *const _,
                     gl::STATIC_DRAW
                 );
-
-                gl::EnableVertexAttribArray(1);
-                gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
-                (vao, m.index_count)
+                layout.apply();
+                (vao, indices.len() as i32)
             }
         }).collect::<Vec<_>>();
         eprintln!("VAOS: {:?}", chunks);
@@ -200,69 +228,38 @@ fn main() {
         //let m = mc::mc_test();
 
 
-        let mut grid_vao = Vec::new();
-        let mut grid_model_mat = Vec::new();
-        let grid_ic = 36;
-        for i in 0..4 {
-            for j in 0..4 {
-                for k in 0..4 {
-                    let m = mc::Mesh::cube(glm::vec3(16.0,16.0,16.0), glm::vec2(1.0,1.0), true, false, glm::vec3(1.0,1.0,1.0), glm::vec4(0.0, 0.0, 0.0, 1.0));
-                    let vertices = m.vertices;
-                    let indices = m.indices;
-                    let normals = m.normals;
-                    let vao = unsafe {
-                        let mut vao = 0;
-                        gl::GenVertexArrays(1, &mut vao);
-                        gl::BindVertexArray(vao);
-
-                        let mut ibo = 0;
-                        gl::GenBuffers(1, &mut ibo);
-                        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ibo);
-                        gl::BufferData(
-                            gl::ELEMENT_ARRAY_BUFFER,
-                            byte_size_of_array(&indices),
-                            pointer_to_array(&indices) as *const _,
-                            gl::STATIC_DRAW
-                        );
-
-                        let mut vbo = 0;
-                        gl::GenBuffers(1, &mut vbo);
-                        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-                        gl::BufferData(
-                            gl::ARRAY_BUFFER,
-                            byte_size_of_array(&vertices),
-                            pointer_to_array(&vertices) as *const _,
-                            gl::STATIC_DRAW
-                        );
-
-                        gl::EnableVertexAttribArray(0);
-                        gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
-
-                        let mut nbo = 0;
-                        gl::GenBuffers(1, &mut nbo);
-                        gl::BindBuffer(gl::ARRAY_BUFFER, nbo);
-                        gl::BufferData(
-                            gl::ARRAY_BUFFER,
-                            byte_size_of_array(&normals),
-                            pointer_to_array(&normals) as *const _,
-                            gl::STATIC_DRAW
-                        );
-
-                        gl::EnableVertexAttribArray(1);
-                        gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
-                        vao
-                    };
-                    grid_vao.push(vao);
-                    grid_model_mat.push(
-                        glm::translate(
-                            &glm::identity(),
-                            &glm::vec3(8.0+16.0*i as f32, 8.0+16.0*j as f32, 8.0+16.0*k as f32)
-                        )
-                    );
-
-                }
-            }
-        }
+        // Chunk bounds as one line list
+        let grid = lines::Lines::chunk_grid(chunk_counts, glm::Vec3::repeat(chunk_size), glm::Vec3::zeros());
+        let grid_ic = grid.indices.len() as i32;
+        let grid_vao = unsafe {
+            let mut vao = 0;
+            gl::GenVertexArrays(1, &mut vao);
+            gl::BindVertexArray(vao);
+
+            let mut ibo = 0;
+            gl::GenBuffers(1, &mut ibo);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ibo);
+            gl::BufferData(
+                gl::ELEMENT_ARRAY_BUFFER,
+                byte_size_of_array(&grid.indices),
+                pointer_to_array(&grid.indices) as *const _,
+                gl::STATIC_DRAW
+            );
+
+            let mut vbo = 0;
+            gl::GenBuffers(1, &mut vbo);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                byte_size_of_array(&grid.vertices),
+                pointer_to_array(&grid.vertices) as *const _,
+                gl::STATIC_DRAW
+            );
+
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
+            vao
+        };
 
         // Basic usage of shader helper
         // The code below returns a shader object, which contains the field .program_id
@@ -275,7 +272,6 @@ fn main() {
                 .link()
         };
         unsafe { sh.activate() };
-        let mut mouse_pos = (0.0, 0.0);
 
         let u_time = unsafe { sh.get_uniform_location("u_time") };
 
@@ -288,10 +284,12 @@ fn main() {
         let u_mvp = unsafe { sh.get_uniform_location("u_mvp") };
         let u_model = unsafe { sh.get_uniform_location("u_model") };
         let u_view = unsafe { sh.get_uniform_location("u_view") };
+        let u_vertex_colors = unsafe { sh.get_uniform_location("u_vertex_colors") };
+        let u_unlit = unsafe { sh.get_uniform_location("u_unlit") };
 
         // Just adjust aspect ratio
         // let mvp = glm::scale(&glm::identity(), &glm::vec3(1.0, (SCREEN_W / SCREEN_H) as _, 1.0));
-        let mvp = glm::ortho(0.0f32, SCREEN_W as _, 0.0, SCREEN_H as _, 0.0, 1.0);
+        let _mvp = glm::ortho(0.0f32, SCREEN_W as _, 0.0, SCREEN_H as _, 0.0, 1.0);
 
         let aspect = SCREEN_W as f32 / SCREEN_H as f32;
 
@@ -308,7 +306,7 @@ fn main() {
         loop {
             let now = std::time::Instant::now();
             let elapsed = now.duration_since(first_frame_time).as_secs_f32();
-            let delta_time = now.duration_since(last_frame_time).as_secs_f32();
+            let _delta_time = now.duration_since(last_frame_time).as_secs_f32();
             last_frame_time = now;
 
             // Handle keyboard input
@@ -332,17 +330,35 @@ fn main() {
 
                 *delta = (0.0, 0.0);
             }
-            let mid = 32.0f32;
-            let view_mat = glm::look_at(&glm::vec3(mid+mid*2.0*elapsed.cos(),mid*2.5,mid+mid*2.0*elapsed.sin()), &glm::vec3(mid,mid,mid), &glm::vec3(0.0, 1.0, 0.0));
-
-            let mvp: glm::TMat4<f32> = perspective_mat * view_mat;
+            // Orbit the middle of the volume
+            let mid = extent.max() / 2.0;
+            let centre = origin + extent / 2.0;
+            let eye = centre + glm::vec3(mid*2.0*elapsed.cos(), mid*1.5, mid*2.0*elapsed.sin());
+            let view_mat = glm::look_at(&eye, &centre, &glm::vec3(0.0, 1.0, 0.0));
+            let model_mat: glm::Mat4 = glm::translation(&origin);
+
+            let mvp: glm::TMat4<f32> = perspective_mat * view_mat * model_mat;
+
+            // Report what is under the cursor when clicked
+            let mut mouse_pos = (0.0, 0.0);
+            if let Ok(mut cursor) = cursor.lock() {
+                mouse_pos = cursor.0;
+                if cursor.1 {
+                    cursor.1 = false;
+                    // In lattice space, as `mvp` includes the model matrix
+                    let ray = ray::Ray::from_screen(mouse_pos.0, mouse_pos.1, SCREEN_W as f32, SCREEN_H as f32, &mvp);
+                    match field::Grid::new(&points, scale).raycast(&ray, 0.4, 500.0) {
+                        Some(hit) => eprintln!("Picked {:?} at {:?}, distance {:.2}", hit.id, (hit.point + origin).as_slice(), hit.distance),
+                        None => eprintln!("Picked nothing"),
+                    }
+                }
+            }
 
             unsafe {
                 gl::ClearColor(0.163, 0.163, 0.163, 1.0);
                 gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
                 // Issue the necessary commands to draw your scene here
-                let model_mat: glm::Mat4 = glm::identity();
                 gl::UniformMatrix4fv(u_view, 1, gl::FALSE, view_mat.as_ptr());
                 gl::UniformMatrix4fv(u_model, 1, gl::FALSE, model_mat.as_ptr());
                 gl::UniformMatrix4fv(u_mvp, 1, gl::FALSE, mvp.as_ptr());
@@ -355,6 +371,8 @@ fn main() {
 
                 gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
                 gl::Disable(gl::CULL_FACE);
+                gl::Uniform1i(u_unlit, 0);
+                gl::Uniform1i(u_vertex_colors, 1);
                 for (vao, ic) in chunks.iter() {
 
                     gl::Uniform4f(u_color, 1.0, 0.0, 1.0, 1.0);
@@ -362,16 +380,10 @@ fn main() {
                     gl::DrawElements(gl::TRIANGLES, *ic, gl::UNSIGNED_INT, std::ptr::null());
                 }
 
-                gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE);
-                for (vao, model) in grid_vao.iter().zip(&grid_model_mat) {
-                    let mvp = mvp * model;
-                    gl::UniformMatrix4fv(u_mvp, 1, gl::FALSE, mvp.as_ptr());
-                    gl::Uniform4f(u_color, 0.0, 0.0, 0.0, 0.5);
-
-                    gl::BindVertexArray(*vao);
-                    gl::DrawElements(gl::TRIANGLES, grid_ic, gl::UNSIGNED_INT, std::ptr::null());
-
-                }
+                gl::Uniform1i(u_unlit, 1);
+                gl::Uniform4f(u_color, 0.0, 0.0, 0.0, 0.5);
+                gl::BindVertexArray(grid_vao);
+                gl::DrawElements(gl::LINES, grid_ic, gl::UNSIGNED_INT, std::ptr::null());
                 gl::Enable(gl::CULL_FACE);
 
 
@@ -386,7 +398,7 @@ fn main() {
     let render_thread_healthy = Arc::new(RwLock::new(true));
     let render_thread_watchdog = Arc::clone(&render_thread_healthy);
     thread::spawn(move || {
-        if !render_thread.join().is_ok() {
+        if render_thread.join().is_err() {
             if let Ok(mut health) = render_thread_watchdog.write() {
                 println!("Render thread panicked!");
                 *health = false;
@@ -400,7 +412,7 @@ fn main() {
 
         // Terminate program if render thread panics
         if let Ok(health) = render_thread_healthy.read() {
-            if *health == false {
+            if !*health {
                 *control_flow = ControlFlow::Exit;
             }
         }
@@ -430,11 +442,20 @@ fn main() {
                 }
 
                 // Handle escape separately
-                match keycode {
-                    Escape => {
-                        *control_flow = ControlFlow::Exit;
-                    },
-                    _ => { }
+                if keycode == Escape {
+                    *control_flow = ControlFlow::Exit;
+                }
+            },
+            // Keep track of the cursor for picking. Positions are in physical pixels, which
+            // match SCREEN_W and SCREEN_H at a scale factor of 1
+            Event::WindowEvent { event: WindowEvent::CursorMoved { position, .. }, .. } => {
+                if let Ok(mut cursor) = arc_cursor.lock() {
+                    cursor.0 = (position.x as f32, position.y as f32);
+                }
+            },
+            Event::WindowEvent { event: WindowEvent::MouseInput { state: Pressed, button: MouseButton::Left, .. }, .. } => {
+                if let Ok(mut cursor) = arc_cursor.lock() {
+                    cursor.1 = true;
                 }
             },
             Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
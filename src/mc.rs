@@ -1,7 +1,7 @@
 // Marching cubes
 
-use noise::{self, NoiseFn};
-use glm;
+use crate::sink::{Triangle, TriangleSink};
+
 
 // Edge and tri table from http://paulbourke.net/geometry/polygonise/
 #[allow(unused)]
@@ -299,31 +299,110 @@ const TRI_TABLE: [[i32;16];256] = [
     [-1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1]
 ];
 
-fn vertex_interp(
+/// Corners joined by each of the 12 cube edges, in `EDGE_TABLE` bit order. 
+/// The lower lattice corner comes first
+const EDGE_CORNERS: [(usize, usize); 12] = [
+    (0, 1), (1, 2), (3, 2), (0, 3),
+    (4, 5), (5, 6), (7, 6), (4, 7),
+    (0, 4), (1, 5), (2, 6), (3, 7),
+];
+
+/// How surface vertices close to a cube corner are placed. Vertices landing 
+/// on or next to a corner give zero-area or sliver triangles
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum SnapPolicy {
+    /// Plain linear interpolation
+    #[default]
+    None,
+    /// Move vertices closer than this fraction of an edge onto the corner. 
+    /// The triangles this collapses are exactly degenerate, and can be 
+    /// dropped with `cleanup::DegenerateFilter`
+    Corner(f32),
+    /// Keep vertices at least this fraction of an edge away from both 
+    /// corners, so no triangle collapses to zero area. Taken as 0.5 above
+    /// that, and as 0 below zero or if NaN
+    Clamp(f32),
+}
+
+/// Texture coordinates generated for extracted meshes, projected from world
+/// positions so they need no parameterisation of the surface
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum TexturePolicy {
+    /// Leave `Mesh::texture_coordinates` empty
+    #[default]
+    None,
+    /// 2 floats per vertex, the position projected along the axis the
+    /// normal is closest to, in units of `scale`
+    DominantAxis { scale: f32 },
+    /// 9 floats per vertex: blend weights for the x, y and z projections,
+    /// summing to 1, followed by the UVs of the x, y and z projections, in
+    /// units of `scale`. Higher `sharpness` narrows the blend between
+    /// projections
+    Triplanar { scale: f32, sharpness: f32 },
+}
+
+/// Options for marching cubes extraction
+#[derive(Clone, Copy, Debug, Default)]
+pub struct McOptions {
+    pub snap: SnapPolicy,
+    /// Only applied by `marching_cubes_with`, as triangle sinks receive
+    /// positions and normals alone
+    pub texture: TexturePolicy,
+}
+
+/// Counts reported by an extraction
+#[derive(Clone, Copy, Debug, Default)]
+pub struct McStats {
+    pub triangles: usize,
+    /// Vertices moved by the snap policy
+    pub snapped: usize,
+}
+
+/// Returns the interpolated point, and whether the snap policy moved it.
+/// Generic over the dimension, so marching squares shares it
+pub fn vertex_interp<const D: usize>(
     isolevel: f64, 
-    p1: glm::TVec3<f32>, 
-    p2: glm::TVec3<f32>, 
+    p1: glm::TVec<f32, D>, 
+    p2: glm::TVec<f32, D>, 
     val1: f64, 
-    val2: f64
-) -> glm::Vec3 {
+    val2: f64,
+    snap: SnapPolicy,
+) -> (glm::TVec<f32, D>, bool) {
     let eps = 0.00001;
-    //eprintln!("interpolate {:?} and {:?}: {:?}", p1, p2, (p1+p2)/2.0);
-    // TODO: Interpolate according to difference in values
-    //(p1 + p2) / 2.0
-    if (isolevel-val1).abs() < eps { return p1 }
-    if (isolevel-val2).abs() < eps { return p2 }
-    if (val1-val2).abs() < eps { return p1 }
-
-    let mu = (isolevel - val1) / (val2 - val1);
-    p1 + mu as f32 * (p2 - p1)
+    let mu = if (isolevel-val1).abs() < eps { 0.0 }
+        else if (isolevel-val2).abs() < eps { 1.0 }
+        else if (val1-val2).abs() < eps { 0.0 }
+        else { ((isolevel - val1) / (val2 - val1)) as f32 };
+
+    let snapped = match snap {
+        SnapPolicy::None => mu,
+        SnapPolicy::Corner(t) if mu < t => 0.0,
+        SnapPolicy::Corner(t) if mu > 1.0 - t => 1.0,
+        SnapPolicy::Corner(_) => mu,
+        // Past 0.5 the bounds cross, which makes clamp panic
+        SnapPolicy::Clamp(t) => {
+            let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 0.5) };
+            mu.clamp(t, 1.0 - t)
+        },
+    };
+    // Corners are returned as is, so that they are bitwise equal
+    let p = if snapped == 0.0 { p1 }
+        else if snapped == 1.0 { p2 }
+        else { p1 + snapped * (p2 - p1) };
+    (p, snapped != mu)
 }
 
+#[allow(unused)]
 #[derive(Default)]
 pub struct Mesh {
     pub vertices: Vec<f32>,
     pub normals: Vec<f32>,
     pub texture_coordinates: Vec<f32>,
     pub colors: Vec<f32>,
+    /// Ambient visibility per vertex, 1 where fully open, see `bake_occlusion`
+    pub occlusion: Vec<f32>,
     pub indices: Vec<u32>,
     pub index_count: i32,
 }
@@ -331,16 +410,46 @@ impl Mesh {
     pub fn new() -> Self { 
         Mesh { ..Default::default() }
     }
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len() / 3
+    }
+    /// Position of vertex `i`
+    pub fn position(&self, i: u32) -> glm::Vec3 {
+        let i = i as usize * 3;
+        glm::vec3(self.vertices[i], self.vertices[i+1], self.vertices[i+2])
+    }
+    /// Unit vertex normals, from `normals` or, if the mesh has none, area
+    /// weighted face normals. Zero where there is no direction to normalise
+    pub fn vertex_normals(&self) -> Vec<glm::Vec3> {
+        let n = self.vertex_count();
+        let normals = if self.normals.len() == self.vertices.len() {
+            (0..n).map(|i| glm::vec3(self.normals[i * 3], self.normals[i * 3 + 1], self.normals[i * 3 + 2])).collect()
+        } else {
+            let mut normals = vec![glm::Vec3::zeros(); n];
+            for t in self.indices.chunks_exact(3) {
+                let p = [self.position(t[0]), self.position(t[1]), self.position(t[2])];
+                let face = glm::cross(&(p[1] - p[0]), &(p[2] - p[0]));
+                t.iter().for_each(|&v| normals[v as usize] += face);
+            }
+            normals
+        };
+        normals.iter().map(|n| if glm::length2(n) > 0.0 { glm::normalize(n) } else { *n }).collect()
+    }
+    /// Box around the origin with sides `scale`, one quad of 6 vertices per
+    /// face, facing inwards if `inverted`. Tiling textures repeat every
+    /// `texture_scale` units, stretched by `texture_scale3d` per axis. The
+    /// colour is not used, see `set_color` and the primitives module
+    #[allow(unused)]
     pub fn cube(
         scale: glm::TVec3<f32>,
         texture_scale: glm::TVec2<f32>,
         tiling_textures: bool,
         inverted: bool,
         texture_scale3d: glm::TVec3<f32>,
-        color: glm::TVec4<f32>
+        _color: glm::TVec4<f32>
     ) -> Self {
         let mut points = [glm::vec3(0.0, 0.0, 0.0); 8];
-        let mut indices = vec![0; 36];
+        let mut indices = [0; 36];
 
         for y in 0..2 {
             for z in 0..2 {
@@ -394,7 +503,7 @@ impl Mesh {
         let mut texture_coordinates = Vec::new();
         for face in 0..6 {
             let offset = face * 6;
-            indices[offset + 0] = faces[face][0] as u32;
+            indices[offset] = faces[face][0] as u32;
             indices[offset + 3] = faces[face][0] as u32;
 
             if !inverted {
@@ -431,7 +540,6 @@ impl Mesh {
                 }
             }
         }
-        let vertex_count = vertices.len();
         Mesh {
             vertices: crate::util::from_array_of_vec3(vertices),
             indices: mindices,
@@ -444,32 +552,37 @@ impl Mesh {
 }
 
 
-fn mc_internal(
+/// `cell` is the lattice coordinate of the cube's lowest corner. Corners are 
+/// computed from lattice coordinates, and each edge is interpolated from its 
+/// lower to its higher corner, so neighbouring cubes produce bitwise equal 
+/// vertices on shared edges
+fn mc_internal<S: TriangleSink + ?Sized>(
     cell: glm::TVec3<f32>, 
     val: [f64; 8],
     scale: f32, 
     isolevel: f64, 
-    mesh: &mut Mesh
-) {
+    opts: &McOptions,
+    sink: &mut S
+) -> McStats {
 
-    let mut vert_list = vec![glm::zero();12];
+    let mut vert_list = [glm::zero();12];
 
     let p = [
-        cell + glm::vec3(0.0,0.0,0.0) * scale,
-        cell + glm::vec3(0.0,1.0,0.0) * scale,
-        cell + glm::vec3(1.0,1.0,0.0) * scale,
-        cell + glm::vec3(1.0,0.0,0.0) * scale,
-        cell + glm::vec3(0.0,0.0,1.0) * scale,
-        cell + glm::vec3(0.0,1.0,1.0) * scale,
-        cell + glm::vec3(1.0,1.0,1.0) * scale,
-        cell + glm::vec3(1.0,0.0,1.0) * scale,
+        (cell + glm::vec3(0.0,0.0,0.0)) * scale,
+        (cell + glm::vec3(0.0,1.0,0.0)) * scale,
+        (cell + glm::vec3(1.0,1.0,0.0)) * scale,
+        (cell + glm::vec3(1.0,0.0,0.0)) * scale,
+        (cell + glm::vec3(0.0,0.0,1.0)) * scale,
+        (cell + glm::vec3(0.0,1.0,1.0)) * scale,
+        (cell + glm::vec3(1.0,1.0,1.0)) * scale,
+        (cell + glm::vec3(1.0,0.0,1.0)) * scale,
     ];
 
     
     /* Compute index */
     // TODO: Let external function decide cmp with isolevel (or just have them invert the isolevel?)
     let cube_idx = (
-          ((val[0] < isolevel) as u8) << 0
+          ((val[0] < isolevel) as u8)
         | ((val[1] < isolevel) as u8) << 1
         | ((val[2] < isolevel) as u8) << 2
         | ((val[3] < isolevel) as u8) << 3
@@ -481,91 +594,33 @@ fn mc_internal(
     //eprintln!("cube idx: {:08b}", cube_idx);
     //eprintln!("edge table: {:08b}", EDGE_TABLE[cube_idx]);
     /* Find the vertices where the surface intersects the cube */
-    if EDGE_TABLE[cube_idx] & 1 != 0 {
-        vert_list[0] = vertex_interp(
-            isolevel,p[0],p[1],val[0],val[1]);
-    }
-    if EDGE_TABLE[cube_idx] & 2 != 0 {
-        vert_list[1] = vertex_interp(
-            isolevel,p[1],p[2],val[1],val[2]
-        );
-    }
-    if EDGE_TABLE[cube_idx] & 4 != 0 {
-        vert_list[2] = vertex_interp(
-            isolevel,p[2],p[3],val[2],val[3]
-        );
-    }
-    //eprintln!("edge table: {:08b} => {}", EDGE_TABLE[cube_idx], EDGE_TABLE[cube_idx] & 8);
-    if EDGE_TABLE[cube_idx] & 8 != 0 {
-        vert_list[3] = vertex_interp(
-            isolevel,p[3],p[0],val[3],val[0]
-        );
-    }
-    if EDGE_TABLE[cube_idx] & 16 != 0 {
-        vert_list[4] = vertex_interp(
-            isolevel,p[4],p[5],val[4],val[5]
-        );
-    }
-    if EDGE_TABLE[cube_idx] & 32 != 0 {
-        vert_list[5] = vertex_interp(
-            isolevel,p[5],p[6],val[5],val[6]
-        );
-    }
-    if EDGE_TABLE[cube_idx] & 64 != 0 {
-        vert_list[6] = vertex_interp(
-            isolevel,p[6],p[7],val[6],val[7]
-        );
-    }
-    if EDGE_TABLE[cube_idx] & 128 != 0 {
-        vert_list[7] = vertex_interp(
-            isolevel,p[7],p[4],val[7],val[4]
-        );
-    }
-    if EDGE_TABLE[cube_idx] & 256 != 0 {
-        vert_list[8] = vertex_interp(
-            isolevel,p[0],p[4],val[0],val[4]
-        );
-    }
-    if EDGE_TABLE[cube_idx] & 512 != 0 {
-        vert_list[9] = vertex_interp(
-            isolevel,p[1],p[5],val[1],val[5]
-        );
-    }
-    if EDGE_TABLE[cube_idx] & 1024 != 0 {
-        vert_list[10] = vertex_interp(
-            isolevel,p[2],p[6],val[2],val[6]
-        );
-    }
-    if EDGE_TABLE[cube_idx] & 2048 != 0 {
-        vert_list[11] = vertex_interp(
-            isolevel,p[3],p[7],val[3],val[7]
-        );
+    let mut snapped = 0;
+    for (e, &(c1, c2)) in EDGE_CORNERS.iter().enumerate() {
+        if EDGE_TABLE[cube_idx] & (1 << e) != 0 {
+            let (v, s) = vertex_interp(
+                isolevel,p[c1],p[c2],val[c1],val[c2],opts.snap
+            );
+            vert_list[e] = v;
+            snapped += s as usize;
+        }
     }
     //eprintln!("vert list: {:?}", vert_list);
 
     /* Look-up triangles */
-    let i0 = mesh.indices.len() as u32;
+    let mut triangles = 0;
     for i in (0..).step_by(3) {
         if TRI_TABLE[cube_idx][i] == -1 { break }
         let t0 = vert_list[TRI_TABLE[cube_idx][i] as usize];
         let t1 = vert_list[TRI_TABLE[cube_idx][i+1] as usize];
         let t2 = vert_list[TRI_TABLE[cube_idx][i+2] as usize];
         let n = glm::cross(&(t0-t1), &(t0-t2));
-        mesh.indices.extend_from_slice(&[i0+i as u32, i0+1+i as u32, i0+2+i as u32]);
-        mesh.vertices.extend_from_slice(&[
-            t0.x, t0.y, t0.z, 
-            t1.x, t1.y, t1.z, 
-            t2.x, t2.y, t2.z,
-        ]);
-        mesh.normals.extend_from_slice(&[
-            n.x, n.y, n.z, 
-            n.x, n.y, n.z, 
-            n.x, n.y, n.z,
-        ]);
-        mesh.index_count += 3;
+        sink.triangle(&Triangle { vertices: [t0, t1, t2], normal: n });
+        triangles += 1;
     }
+    McStats { triangles, snapped }
 }
 
+#[allow(unused)]
 pub fn mc_test() -> Mesh {
     let mut m = Mesh::new();
     // let grid = [
@@ -592,7 +647,7 @@ pub fn mc_test() -> Mesh {
                     grid[i+1][j+1][k+1],
                     grid[i+1][j][k+1],
                 ];
-                mc_internal(glm::vec3(i as f32, j as f32, k as f32), val, 1.0, 0.5, &mut m);
+                mc_internal(glm::vec3(i as f32, j as f32, k as f32), val, 1.0, 0.5, &McOptions::default(), &mut m);
             }
         }
     }
@@ -607,18 +662,41 @@ pub fn mc_test() -> Mesh {
 
 /// Marching cubes with vertices duplicated: Simplicity of implementation and 
 /// flat shading. Starts at voxel coordinates c0 
-pub fn marching_cubes(c0: (usize, usize, usize), scale: f32, points: &Vec<Vec<Vec<f64>>>, isolevel: f64) -> Mesh {
-    
+pub fn marching_cubes(c0: (usize, usize, usize), scale: f32, points: &[Vec<Vec<f64>>], isolevel: f64) -> Mesh {
+    marching_cubes_with(c0, scale, points, isolevel, &McOptions::default())
+}
+
+/// `marching_cubes` with options, including generated texture coordinates
+pub fn marching_cubes_with(
+    c0: (usize, usize, usize), 
+    scale: f32, 
+    points: &[Vec<Vec<f64>>], 
+    isolevel: f64, 
+    opts: &McOptions,
+) -> Mesh {
     let mut mesh = Mesh::new();
+    marching_cubes_into(c0, scale, points, isolevel, opts, &mut mesh);
+    mesh.generate_texture_coordinates(opts.texture);
+    mesh
+}
 
+/// Marching cubes streaming every triangle into `sink` instead of collecting 
+/// a `Mesh`. Starts at voxel coordinates c0 
+pub fn marching_cubes_into<S: TriangleSink + ?Sized>(
+    c0: (usize, usize, usize), 
+    scale: f32, 
+    points: &[Vec<Vec<f64>>], 
+    isolevel: f64, 
+    opts: &McOptions,
+    sink: &mut S
+) -> McStats {
+    let mut stats = McStats::default();
     let size = (16, 16, 16);
 
-    let perlin = noise::Perlin::new();
-
     for i in c0.0..c0.0+size.0 {
         for j in c0.1..c0.1+size.1 {
             for k in c0.2..c0.2+size.2 {
-                let cell = glm::vec3(i as f32,j as f32,k as f32) * scale;
+                let cell = glm::vec3(i as f32,j as f32,k as f32);
                 //eprintln!("cell: {:?}", cell);
                 //let (i,j,k) = (i as f64 * res, j as f64 * res, k as f64 * res);
                 /* Compute point values */
@@ -634,15 +712,37 @@ pub fn marching_cubes(c0: (usize, usize, usize), scale: f32, points: &Vec<Vec<Ve
                 ]} else { [1.0;8] };
                 //eprintln!("val: {:?}", val);
                 /* MC step */
-                mc_internal(
+                let s = mc_internal(
                     cell,
                     val,
                     scale,
                     isolevel,
-                    &mut mesh,
+                    opts,
+                    sink,
                 );
+                stats.triangles += s.triangles;
+                stats.snapped += s.snapped;
             }
         }
     }
-    mesh
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamp_snap_out_of_range_does_not_panic() {
+        let (a, b) = (glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 0.0, 0.0));
+        for t in [0.8, 1.5, -0.2, f32::NAN] {
+            for val2 in [0.1, 0.5, 0.9] {
+                let (p, _) = vertex_interp(0.05, a, b, 0.0, val2, SnapPolicy::Clamp(t));
+                assert!(p.x >= 0.0 && p.x <= 1.0);
+            }
+        }
+        // Above 0.5 every vertex lands on the edge's midpoint
+        let (p, snapped) = vertex_interp(0.05, a, b, 0.0, 1.0, SnapPolicy::Clamp(0.8));
+        assert_eq!((p.x, snapped), (0.5, true));
+    }
 }
@@ -0,0 +1,224 @@
+// Geometric measurements: area, enclosed volume, centroid, bounds, inertia
+
+use crate::mc::Mesh;
+use crate::sink::{Triangle, TriangleSink};
+use crate::util::vec3_f32_to_f64;
+
+/// Surface and volume integrals accumulated triangle by triangle, in f64.
+/// Volume terms come from the divergence theorem, as signed tetrahedra
+/// against the origin. The integrals are additive, so the properties of
+/// chunks can be merged and give the same result as the whole surface,
+/// even though each chunk on its own is open
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MassProperties {
+    pub triangles: usize,
+    pub area: f64,
+    /// Signed enclosed volume, positive when triangles wind counterclockwise
+    /// seen from outside
+    pub volume: f64,
+    /// Axis-aligned bounds of all vertices
+    pub bounds: Option<(glm::Vec3, glm::Vec3)>,
+    /// Integral of position over the surface
+    area_moment: glm::DVec3,
+    /// Integral of position over the volume
+    volume_moment: glm::DVec3,
+    /// Integral of x x^T over the volume
+    second_moment: glm::DMat3,
+}
+
+#[allow(unused)]
+impl MassProperties {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add_triangle(&mut self, v: &[glm::Vec3; 3]) {
+        let [a, b, c] = v.map(|p| vec3_f32_to_f64(&p));
+        let cross = glm::cross(&(b - a), &(c - a));
+        let area = 0.5 * glm::length(&cross);
+        self.triangles += 1;
+        self.area += area;
+        self.area_moment += (a + b + c) * (area / 3.0);
+
+        // Tetrahedron (0, a, b, c)
+        let det = glm::dot(&a, &glm::cross(&b, &c));
+        let volume = det / 6.0;
+        let sum = a + b + c;
+        self.volume += volume;
+        self.volume_moment += sum * (volume / 4.0);
+        self.second_moment += (a * a.transpose() + b * b.transpose() + c * c.transpose()
+            + sum * sum.transpose()) * (volume / 20.0);
+
+        for p in v.iter() {
+            self.bounds = Some(match self.bounds {
+                Some((min, max)) => (glm::min2(&min, p), glm::max2(&max, p)),
+                None => (*p, *p),
+            });
+        }
+    }
+
+    /// Properties of the union of two disjoint sets of triangles
+    pub fn merge(&self, o: &MassProperties) -> MassProperties {
+        MassProperties {
+            triangles: self.triangles + o.triangles,
+            area: self.area + o.area,
+            volume: self.volume + o.volume,
+            bounds: match (self.bounds, o.bounds) {
+                (Some(a), Some(b)) => Some((glm::min2(&a.0, &b.0), glm::max2(&a.1, &b.1))),
+                (a, b) => a.or(b),
+            },
+            area_moment: self.area_moment + o.area_moment,
+            volume_moment: self.volume_moment + o.volume_moment,
+            second_moment: self.second_moment + o.second_moment,
+        }
+    }
+
+    /// Centroid of the enclosed volume. Only meaningful for closed surfaces
+    pub fn centroid(&self) -> Option<glm::DVec3> {
+        if self.volume == 0.0 { return None }
+        Some(self.volume_moment / self.volume)
+    }
+
+    /// Area weighted centroid of the surface itself
+    pub fn surface_centroid(&self) -> Option<glm::DVec3> {
+        if self.area == 0.0 { return None }
+        Some(self.area_moment / self.area)
+    }
+
+    /// Inertia tensor of the enclosed volume at unit density, about the
+    /// centroid. Only meaningful for closed surfaces
+    pub fn inertia_tensor(&self) -> Option<glm::DMat3> {
+        let c = self.centroid()?;
+        let second = self.second_moment - c * c.transpose() * self.volume;
+        Some(glm::DMat3::identity() * second.trace() - second)
+    }
+}
+
+impl TriangleSink for MassProperties {
+    fn triangle(&mut self, t: &Triangle) {
+        self.add_triangle(&t.vertices);
+    }
+}
+
+#[allow(unused)]
+impl Mesh {
+    pub fn mass_properties(&self) -> MassProperties {
+        let mut props = MassProperties::new();
+        for t in self.indices.chunks_exact(3) {
+            props.add_triangle(&[self.position(t[0]), self.position(t[1]), self.position(t[2])]);
+        }
+        props
+    }
+
+    pub fn area(&self) -> f64 {
+        self.mass_properties().area
+    }
+
+    /// Signed enclosed volume. See `MassProperties::volume`
+    pub fn volume(&self) -> f64 {
+        self.mass_properties().volume
+    }
+
+    pub fn centroid(&self) -> Option<glm::DVec3> {
+        self.mass_properties().centroid()
+    }
+
+    /// Axis-aligned bounds of all vertices, referenced or not
+    pub fn bounds(&self) -> Option<(glm::Vec3, glm::Vec3)> {
+        (0..self.vertex_count() as u32).map(|i| self.position(i)).fold(None, |b, p| Some(match b {
+            Some((min, max)) => (glm::min2(&min, &p), glm::max2(&max, &p)),
+            None => (p, p),
+        }))
+    }
+
+    pub fn inertia_tensor(&self) -> Option<glm::DMat3> {
+        self.mass_properties().inertia_tensor()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() <= tolerance * b.abs().max(1.0), "{a} != {b}");
+    }
+
+    fn assert_close_mat(a: &glm::DMat3, b: &glm::DMat3, tolerance: f64) {
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert_close(*x, *y, tolerance);
+        }
+    }
+
+    #[test]
+    fn box_matches_closed_form() {
+        let offset = glm::vec3(1.0, -2.0, 3.0);
+        let mut mesh = Mesh::cube(glm::vec3(2.0, 3.0, 4.0), glm::vec2(1.0, 1.0), false, false, glm::vec3(1.0, 1.0, 1.0), glm::Vec4::zeros());
+        mesh.transform(&glm::translation(&offset));
+        assert_close(mesh.volume(), 24.0, 1e-6);
+        assert_close(mesh.area(), 2.0 * (6.0 + 8.0 + 12.0), 1e-6);
+        assert!(glm::distance(&mesh.centroid().unwrap(), &vec3_f32_to_f64(&offset)) < 1e-6);
+        // m (b^2 + c^2) / 12 and so on, with m = 24
+        let expected = glm::DMat3::from_diagonal(&glm::vec3(2.0 * (9.0 + 16.0), 2.0 * (4.0 + 16.0), 2.0 * (4.0 + 9.0)));
+        assert_close_mat(&mesh.inertia_tensor().unwrap(), &expected, 1e-5);
+        let (min, max) = mesh.bounds().unwrap();
+        assert_eq!((min, max), (offset - glm::vec3(1.0, 1.5, 2.0), offset + glm::vec3(1.0, 1.5, 2.0)));
+    }
+
+    #[test]
+    fn icosahedron_matches_closed_form() {
+        let radius = 2.0;
+        let mesh = Mesh::icosphere(radius as f32, 0);
+        let phi = (1.0 + 5f64.sqrt()) / 2.0;
+        // Edge length from the circumradius
+        let a = 2.0 * radius / (phi * 5f64.sqrt()).sqrt();
+        let volume = 5.0 / 12.0 * (3.0 + 5f64.sqrt()) * a.powi(3);
+        assert_close(mesh.volume(), volume, 1e-5);
+        assert_close(mesh.area(), 5.0 * 3f64.sqrt() * a * a, 1e-5);
+        assert!(glm::length(&mesh.centroid().unwrap()) < 1e-6);
+        let inertia = glm::DMat3::identity() * (phi * phi / 10.0 * volume * a * a);
+        assert_close_mat(&mesh.inertia_tensor().unwrap(), &inertia, 1e-5);
+    }
+
+    #[test]
+    fn icosphere_approaches_sphere() {
+        let mesh = Mesh::icosphere(1.0, 4);
+        let volume = 4.0 / 3.0 * std::f64::consts::PI;
+        // Inscribed, so slightly smaller than the sphere
+        assert!(mesh.volume() < volume);
+        assert_close(mesh.volume(), volume, 5e-3);
+        assert_close(mesh.area(), 4.0 * std::f64::consts::PI, 5e-3);
+        assert!(glm::length(&mesh.centroid().unwrap()) < 1e-6);
+        assert_close_mat(&mesh.inertia_tensor().unwrap(), &(glm::DMat3::identity() * (0.4 * volume)), 1e-2);
+    }
+
+    #[test]
+    fn merged_properties_match_combined_mesh() {
+        let mut mesh = Mesh::icosphere(1.0, 2);
+        let mut cube = Mesh::cube(glm::vec3(1.0, 2.0, 3.0), glm::vec2(1.0, 1.0), false, false, glm::vec3(1.0, 1.0, 1.0), glm::Vec4::zeros());
+        cube.transform(&glm::translation(&glm::vec3(3.0, 0.5, -1.0)));
+        mesh.append(&cube);
+
+        let whole = mesh.mass_properties();
+        // Split part way through the sphere, so both halves are open
+        let split = 3 * 100;
+        let (a, b) = mesh.indices.split_at(split);
+        let [a, b] = [a, b].map(|indices| {
+            let mut props = MassProperties::new();
+            for t in indices.chunks_exact(3) {
+                props.add_triangle(&[mesh.position(t[0]), mesh.position(t[1]), mesh.position(t[2])]);
+            }
+            props
+        });
+        let merged = a.merge(&b);
+
+        assert_eq!(merged.triangles, whole.triangles);
+        assert_eq!(merged.bounds, whole.bounds);
+        assert_close(merged.area, whole.area, 1e-12);
+        assert_close(merged.volume, whole.volume, 1e-12);
+        assert!(glm::distance(&merged.centroid().unwrap(), &whole.centroid().unwrap()) < 1e-12);
+        assert!(glm::distance(&merged.surface_centroid().unwrap(), &whole.surface_centroid().unwrap()) < 1e-12);
+        assert_close_mat(&merged.inertia_tensor().unwrap(), &whole.inertia_tensor().unwrap(), 1e-12);
+    }
+}
@@ -0,0 +1,82 @@
+// Merging chunk meshes into one welded mesh
+
+use crate::mc::Mesh;
+
+/// Merge chunk meshes from `marching_cubes` into one welded mesh. Chunks
+/// extracted with the same `scale` from the same lattice produce bitwise
+/// equal vertices on their shared faces, so welding the concatenated chunks
+/// closes the seams exactly. Texture coordinates, colours and occlusion are
+/// kept where every chunk has them with the same number of components per
+/// vertex, and dropped otherwise. Normals are recomputed, as by `weld`
+#[allow(unused)]
+pub fn merge_chunks<'a, I>(chunks: I) -> Mesh
+where
+    I: IntoIterator<Item = &'a Mesh>,
+{
+    let chunks = chunks.into_iter().filter(|m| m.vertex_count() > 0).collect::<Vec<_>>();
+    // Components per vertex of an attribute, if all chunks agree on it
+    let components = |attr: fn(&Mesh) -> &Vec<f32>| {
+        let k = chunks.first().map(|m| attr(m).len() / m.vertex_count())?;
+        let agree = chunks.iter().all(|m| attr(m).len() == k * m.vertex_count());
+        (k > 0 && agree).then_some(k)
+    };
+    let texture_coordinates = components(|m| &m.texture_coordinates);
+    let colors = components(|m| &m.colors);
+    let occlusion = components(|m| &m.occlusion);
+
+    let mut merged = Mesh::new();
+    for mesh in &chunks {
+        let base = merged.vertex_count() as u32;
+        merged.vertices.extend_from_slice(&mesh.vertices);
+        if texture_coordinates.is_some() { merged.texture_coordinates.extend_from_slice(&mesh.texture_coordinates) }
+        if colors.is_some() { merged.colors.extend_from_slice(&mesh.colors) }
+        if occlusion.is_some() { merged.occlusion.extend_from_slice(&mesh.occlusion) }
+        merged.indices.extend(mesh.indices.iter().map(|i| base + i));
+    }
+    merged.index_count = merged.indices.len() as i32;
+    merged.weld()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::marching_cubes;
+
+    /// Sphere of radius 6 around lattice point (16, 8, 8) of a 33 by 17 by
+    /// 17 lattice, so it straddles the chunks at x = 0 and x = 16
+    fn straddling_sphere() -> Vec<Vec<Vec<f64>>> {
+        (0..33).map(|i| (0..17).map(|j| (0..17).map(|k| {
+            ((i as f64 - 16.0).powi(2) + (j as f64 - 8.0).powi(2) + (k as f64 - 8.0).powi(2)).sqrt() - 6.0
+        }).collect()).collect()).collect()
+    }
+
+    #[test]
+    fn adjacent_chunks_merge_watertight() {
+        let points = straddling_sphere();
+        let chunks = [(0, 0, 0), (16, 0, 0)].map(|c| marching_cubes(c, 0.5, &points, 0.0));
+        for chunk in &chunks {
+            assert!(!chunk.weld().validate().is_watertight());
+        }
+        let merged = merge_chunks(&chunks);
+        let report = merged.validate();
+        assert!(report.is_watertight(), "{:?}", report);
+        // The seam's vertices are shared rather than duplicated
+        assert!(merged.vertex_count() < chunks.iter().map(|c| c.weld().vertex_count()).sum());
+        let expected = 4.0 / 3.0 * std::f32::consts::PI * 3.0f32.powi(3);
+        assert!((merged.volume() as f32 / expected - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn attributes_are_kept_only_when_every_chunk_has_them() {
+        let points = straddling_sphere();
+        let mut chunks = [(0, 0, 0), (16, 0, 0)].map(|c| marching_cubes(c, 0.5, &points, 0.0));
+        for chunk in chunks.iter_mut() {
+            chunk.occlusion = vec![0.5; chunk.vertex_count()];
+        }
+        chunks[0].colors = vec![1.0; chunks[0].vertex_count() * 4];
+        let merged = merge_chunks(&chunks);
+        assert_eq!(merged.occlusion, vec![0.5; merged.vertex_count()]);
+        assert!(merged.colors.is_empty());
+        assert_eq!(merged.validate_attributes(), Ok(merged.vertex_count()));
+    }
+}
@@ -0,0 +1,132 @@
+// Baked per-vertex ambient occlusion
+
+use rayon::prelude::*;
+
+use crate::bvh::{Bvh, BvhOptions};
+use crate::field::ScalarField;
+use crate::mc::Mesh;
+use crate::ray::Ray;
+
+/// What blocks the hemisphere above each vertex
+#[allow(unused)]
+#[derive(Clone, Copy)]
+pub enum OcclusionMethod<'a> {
+    /// Step along each direction through the field, blocked at the first
+    /// sample inside the surface. Cheap, and sees geometry outside the mesh,
+    /// e.g. in neighbouring chunks
+    Field { field: &'a (dyn ScalarField + Sync), isolevel: f64, steps: usize },
+    /// Cast rays against the mesh itself
+    Rays,
+}
+
+#[derive(Clone, Copy)]
+pub struct OcclusionOptions<'a> {
+    pub method: OcclusionMethod<'a>,
+    /// Directions per vertex, cosine distributed over the hemisphere
+    pub samples: usize,
+    /// Occluders further away than this are ignored
+    pub radius: f32,
+}
+
+/// Cosine weighted directions around +z, from the Hammersley set
+fn hemisphere(samples: usize) -> Vec<glm::Vec3> {
+    (0..samples).map(|i| {
+        let u = (i as f32 + 0.5) / samples as f32;
+        let v = (i as u32).reverse_bits() as f32 / 2f32.powi(32);
+        let (r, phi) = (u.sqrt(), v * std::f32::consts::TAU);
+        glm::vec3(r * phi.cos(), r * phi.sin(), (1.0 - u).sqrt())
+    }).collect()
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Fill `occlusion` with the fraction of the hemisphere around each
+    /// vertex normal left open within the radius, replacing any there
+    pub fn bake_occlusion(&mut self, opts: &OcclusionOptions) {
+        let directions = hemisphere(opts.samples.max(1));
+        let normals = self.vertex_normals();
+        let bvh = matches!(opts.method, OcclusionMethod::Rays).then(|| Bvh::build(self, &BvhOptions::default()));
+        // Lift ray origins off the surface so they miss their own triangles
+        let bias = opts.radius * 1e-4;
+
+        self.occlusion = (0..self.vertex_count()).into_par_iter().map(|i| {
+            let (p, n) = (self.position(i as u32), normals[i]);
+            if glm::length2(&n) == 0.0 { return 1.0 }
+            // Frame around the normal, turned by the golden angle per vertex
+            // so neighbours sample different directions
+            let a = if n.x.abs() < 0.9 { glm::Vec3::x() } else { glm::Vec3::y() };
+            let u = glm::normalize(&(a - n * glm::dot(&a, &n)));
+            let turn = i as f32 * 2.399_963;
+            let u = glm::rotate_vec3(&u, turn, &n);
+            let v = glm::cross(&n, &u);
+
+            let blocked = directions.iter().filter(|d| {
+                let dir = u * d.x + v * d.y + n * d.z;
+                match opts.method {
+                    OcclusionMethod::Field { field, isolevel, steps } => {
+                        let steps = steps.max(1);
+                        (1..=steps).any(|s| {
+                            let t = opts.radius * s as f32 / steps as f32;
+                            field.value(p + dir * t) < isolevel
+                        })
+                    }
+                    OcclusionMethod::Rays => bvh.as_ref()
+                        .is_some_and(|bvh| bvh.raycast(&Ray::new(p + n * bias, dir), opts.radius).is_some()),
+                }
+            }).count();
+            1.0 - blocked as f32 / directions.len() as f32
+        }).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Floor of 2 by 2 at y = 0, facing up
+    fn floor() -> Mesh {
+        Mesh::plane(glm::vec2(2.0, 2.0), (4, 4))
+    }
+
+    /// Floor with a wider roof at y = 0.5 facing down onto it
+    fn sheltered_floor() -> Mesh {
+        let mut mesh = floor();
+        let mut roof = Mesh::plane(glm::vec2(8.0, 8.0), (1, 1));
+        roof.transform(&glm::rotation(std::f32::consts::PI, &glm::Vec3::x()));
+        roof.transform(&glm::translation(&glm::vec3(0.0, 0.5, 0.0)));
+        mesh.append(&roof);
+        mesh
+    }
+
+    fn floor_occlusion(mut mesh: Mesh, method: OcclusionMethod) -> Vec<f32> {
+        let floor_vertices = floor().vertex_count();
+        mesh.bake_occlusion(&OcclusionOptions { method, samples: 64, radius: 2.0 });
+        assert_eq!(mesh.occlusion.len(), mesh.vertex_count());
+        mesh.occlusion.truncate(floor_vertices);
+        mesh.occlusion
+    }
+
+    #[test]
+    fn open_plane_is_fully_open() {
+        let open = |_: glm::Vec3| 1.0;
+        for method in [OcclusionMethod::Rays, OcclusionMethod::Field { field: &open, isolevel: 0.0, steps: 8 }] {
+            assert!(floor_occlusion(floor(), method).iter().all(|&o| o == 1.0));
+        }
+    }
+
+    #[test]
+    fn overhang_occludes_plane_below() {
+        // Solid above y = 0.5, matching the roof
+        let roof = |p: glm::Vec3| if p.y > 0.5 { -1.0 } else { 1.0 };
+        let methods = [
+            OcclusionMethod::Rays,
+            OcclusionMethod::Field { field: &roof, isolevel: 0.0, steps: 8 },
+            // A single step still samples the far end of each direction
+            OcclusionMethod::Field { field: &roof, isolevel: 0.0, steps: 0 },
+        ];
+        for method in methods {
+            let occlusion = floor_occlusion(sheltered_floor(), method);
+            assert!(occlusion.iter().all(|&o| o < 1.0), "{occlusion:?}");
+        }
+    }
+}
@@ -0,0 +1,344 @@
+// Adaptive extraction over an octree of the sampled volume
+
+use std::collections::HashSet;
+
+use crate::field::{Grid, ScalarField};
+use crate::mc::Mesh;
+
+#[derive(Clone, Copy, Debug)]
+pub struct OctreeOptions {
+    /// Largest allowed difference between a sample and the trilinear
+    /// interpolation of the corners of the leaf containing it
+    pub max_error: f64,
+    /// Smallest leaf size in cells
+    pub min_size: usize,
+    /// Leaves containing the surface are subdivided down to at most this
+    /// many cells, however flat
+    pub max_size: usize,
+}
+impl Default for OctreeOptions {
+    fn default() -> Self {
+        OctreeOptions {
+            max_error: 0.02,
+            min_size: 1,
+            max_size: 8,
+        }
+    }
+}
+
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OctreeStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    /// Leaves with a vertex
+    pub surface_leaves: usize,
+    pub triangles: usize,
+}
+
+struct Node {
+    /// Lattice coordinates of the lower corner
+    min: [usize; 3],
+    /// Size in cells
+    size: usize,
+    /// Index of the first of 8 children, ordered as in `child_offset`
+    children: Option<usize>,
+    vertex: Option<u32>,
+}
+
+fn child_offset(c: usize) -> [usize; 3] {
+    [(c >> 2) & 1, (c >> 1) & 1, c & 1]
+}
+
+/// Lattice coordinates of corner `c` of the node at `min` with `size`
+fn corner(min: [usize; 3], size: usize, c: usize) -> [usize; 3] {
+    let o = child_offset(c);
+    [min[0] + o[0] * size, min[1] + o[1] * size, min[2] + o[2] * size]
+}
+
+struct Octree<'a> {
+    points: &'a [Vec<Vec<f64>>],
+    isolevel: f64,
+    opts: OctreeOptions,
+    nodes: Vec<Node>,
+}
+
+impl Octree<'_> {
+    fn sample(&self, p: [usize; 3]) -> f64 {
+        self.points[p[0]][p[1]][p[2]]
+    }
+
+    /// Whether the node must be split: the surface passes through it, and
+    /// its corners alone do not describe the samples inside well enough
+    fn needs_split(&self, min: [usize; 3], size: usize) -> bool {
+        if size <= self.opts.min_size.max(1) { return false }
+        let c = (0..8).map(|c| self.sample(corner(min, size, c))).collect::<Vec<_>>();
+        let (mut below, mut above, mut error, mut mismatch) = (false, false, 0.0f64, false);
+        for i in 0..=size {
+            for j in 0..=size {
+                for k in 0..=size {
+                    let v = self.sample([min[0] + i, min[1] + j, min[2] + k]);
+                    let inside = v < self.isolevel;
+                    below |= inside;
+                    above |= !inside;
+                    let (x, y, z) = (i as f64 / size as f64, j as f64 / size as f64, k as f64 / size as f64);
+                    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+                    let t = lerp(
+                        lerp(lerp(c[0], c[1], z), lerp(c[2], c[3], z), y),
+                        lerp(lerp(c[4], c[5], z), lerp(c[6], c[7], z), y),
+                        x,
+                    );
+                    error = error.max((t - v).abs());
+                    mismatch |= (t < self.isolevel) != inside;
+                }
+            }
+        }
+        below && above && (size > self.opts.max_size || mismatch || error > self.opts.max_error)
+    }
+
+    /// Refine the node recursively. Children are stored contiguously
+    fn split(&mut self, index: usize) {
+        let (min, size) = (self.nodes[index].min, self.nodes[index].size);
+        if !self.needs_split(min, size) { return }
+        let first = self.nodes.len();
+        let half = size / 2;
+        for c in 0..8 {
+            self.nodes.push(Node {
+                min: corner(min, half, c),
+                size: half,
+                children: None,
+                vertex: None,
+            });
+        }
+        self.nodes[index].children = Some(first);
+        for c in 0..8 {
+            self.split(first + c);
+        }
+    }
+
+    /// Leaf containing the lattice-space point, if inside the tree
+    fn leaf_at(&self, p: [f64; 3]) -> Option<usize> {
+        let root = &self.nodes[0];
+        let inside = (0..3).all(|c| p[c] >= root.min[c] as f64 && p[c] <= (root.min[c] + root.size) as f64);
+        if !inside { return None }
+        let mut i = 0;
+        while let Some(first) = self.nodes[i].children {
+            let n = &self.nodes[i];
+            let mid = n.min.map(|m| (m + n.size / 2) as f64);
+            let c = ((p[0] >= mid[0]) as usize) << 2 | ((p[1] >= mid[1]) as usize) << 1 | (p[2] >= mid[2]) as usize;
+            i = first + c;
+        }
+        Some(i)
+    }
+}
+
+/// Vertex of a leaf: minimiser of the quadric error of the planes through
+/// the crossings on its edges, kept inside the leaf, in lattice space.
+/// `grid` must have unit scale
+fn leaf_vertex(tree: &Octree, grid: &Grid, n: &Node) -> glm::Vec3 {
+    let lo = glm::vec3(n.min[0] as f64, n.min[1] as f64, n.min[2] as f64);
+    let hi = lo + glm::DVec3::repeat(n.size as f64);
+    let mut crossings = Vec::new();
+    for a in 0..8 {
+        for axis in 0..3 {
+            let b = a | (4 >> axis);
+            if b == a { continue }
+            let (pa, pb) = (corner(n.min, n.size, a), corner(n.min, n.size, b));
+            let (va, vb) = (tree.sample(pa), tree.sample(pb));
+            if (va < tree.isolevel) == (vb < tree.isolevel) { continue }
+            let mu = (tree.isolevel - va) / (vb - va);
+            let to = |p: [usize; 3]| glm::vec3(p[0] as f64, p[1] as f64, p[2] as f64);
+            crossings.push(to(pa) + (to(pb) - to(pa)) * mu);
+        }
+    }
+    if crossings.is_empty() {
+        let c = (lo + hi) * 0.5;
+        return glm::vec3(c.x as f32, c.y as f32, c.z as f32)
+    }
+    let mass = crossings.iter().sum::<glm::DVec3>() / crossings.len() as f64;
+    let (mut ata, mut atb) = (glm::DMat3::zeros(), glm::DVec3::zeros());
+    for p in &crossings {
+        let g = grid.gradient(p.cast(), 0.5);
+        if glm::length2(&g) == 0.0 { continue }
+        let normal = glm::normalize(&g);
+        ata += normal * normal.transpose();
+        atb += normal * glm::dot(&normal, &(p - mass));
+    }
+    // Pseudo-inverse dropping small singular values, so flat and
+    // cylindrical regions stay near the mass point
+    let x = ata.svd(true, true).solve(&atb, 0.1 * ata.norm()).map_or(mass, |d| mass + d);
+    let inside = (0..3).all(|c| x[c] >= lo[c] && x[c] <= hi[c]);
+    let x = if inside { x } else { mass };
+    glm::vec3(x.x as f32, x.y as f32, x.z as f32)
+}
+
+/// Adaptive extraction of the `size`³ cells from voxel `c0`. Fails unless
+/// `size` is a power of two and the cells lie inside `points`. An octree is
+/// refined only where the surface passes and the field is not well
+/// described by the corners of a larger cell, and the surface is built by
+/// dual contouring over its leaves: one vertex per leaf the surface passes
+/// through, joined across every crossed edge of the smallest leaf around
+/// that edge. Leaves of different sizes share vertices, so there are no
+/// cracks between levels. With `min_size` above 1, features smaller than
+/// the smallest leaves can be lost or leave holes, as refinement stops
+/// before it finds them. Like any dual method it can give non-manifold
+/// edges where one leaf holds several sheets of surface, as in noisy
+/// fields. Positions use the same lattice and `scale` as `marching_cubes`,
+/// so results are directly comparable
+#[allow(unused)]
+pub fn octree_extract(
+    c0: (usize, usize, usize),
+    size: usize,
+    scale: f32,
+    points: &[Vec<Vec<f64>>],
+    isolevel: f64,
+    opts: &OctreeOptions,
+) -> Result<(Mesh, OctreeStats), String> {
+    if !size.is_power_of_two() {
+        return Err(format!("octree size {} is not a power of two", size));
+    }
+    let (nx, ny, nz) = Grid::new(points, 1.0).dims();
+    if c0.0 + size >= nx || c0.1 + size >= ny || c0.2 + size >= nz {
+        return Err(format!("{} cells from {:?} do not fit in {}x{}x{} points", size, c0, nx, ny, nz));
+    }
+    let mut tree = Octree { points, isolevel, opts: *opts, nodes: Vec::new() };
+    tree.nodes.push(Node { min: [c0.0, c0.1, c0.2], size, children: None, vertex: None });
+    tree.split(0);
+    let grid = Grid::new(points, 1.0);
+
+    let mut mesh = Mesh::new();
+    let mut stats = OctreeStats { nodes: tree.nodes.len(), ..Default::default() };
+    for i in 0..tree.nodes.len() {
+        if tree.nodes[i].children.is_some() { continue }
+        stats.leaves += 1;
+        // With `min_size` 1, refinement guarantees that a leaf with a
+        // crossed edge of any neighbour on its boundary has corners on
+        // both sides. Larger leaves at `min_size` are not refined to check
+        let n = &tree.nodes[i];
+        let inside = (0..8).map(|c| tree.sample(corner(n.min, n.size, c)) < isolevel).collect::<Vec<_>>();
+        if inside.iter().all(|&x| x) || inside.iter().all(|&x| !x) { continue }
+        let p = leaf_vertex(&tree, &grid, n) * scale;
+        tree.nodes[i].vertex = Some(mesh.vertex_count() as u32);
+        mesh.vertices.extend_from_slice(&[p.x, p.y, p.z]);
+        stats.surface_leaves += 1;
+    }
+
+    // Every crossed edge of every leaf, joined by the leaf vertices around
+    // it when this leaf is the smallest there
+    let mut done = HashSet::new();
+    for i in 0..tree.nodes.len() {
+        let n = &tree.nodes[i];
+        if n.children.is_some() || n.vertex.is_none() { continue }
+        for a in 0..8 {
+            for axis in 0..3 {
+                if a & (4 >> axis) != 0 { continue }
+                let pa = corner(n.min, n.size, a);
+                let mut pb = pa;
+                pb[axis] += n.size;
+                let inside = tree.sample(pa) < isolevel;
+                if inside == (tree.sample(pb) < isolevel) { continue }
+                let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                let mut mid = pa.map(|x| x as f64);
+                mid[axis] += n.size as f64 * 0.5;
+                // Leaves around the edge, counter-clockwise seen from +axis
+                let around = [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)].map(|(du, dv)| {
+                    let mut q = mid;
+                    q[u] += du;
+                    q[v] += dv;
+                    tree.leaf_at(q)
+                });
+                if around.iter().any(|l| l.is_none()) { continue }
+                let around = around.map(|l| l.unwrap());
+                if around.iter().any(|&l| tree.nodes[l].size < n.size) { continue }
+                if !done.insert((pa, axis, n.size)) { continue }
+                let mut quad = around.iter().filter_map(|&l| tree.nodes[l].vertex).collect::<Vec<_>>();
+                quad.dedup();
+                if quad.len() > 1 && quad[0] == quad[quad.len() - 1] { quad.pop(); }
+                // Outward normal along +axis when the lower end is inside
+                if !inside { quad.reverse() }
+                let tris: &[[usize; 3]] = match quad.len() {
+                    3 => &[[0, 1, 2]],
+                    4 => {
+                        let d = |a: usize, b: usize| glm::distance2(&mesh.position(quad[a]), &mesh.position(quad[b]));
+                        if d(0, 2) <= d(1, 3) { &[[0, 1, 2], [0, 2, 3]] } else { &[[0, 1, 3], [1, 2, 3]] }
+                    },
+                    _ => &[],
+                };
+                for t in tris {
+                    mesh.indices.extend(t.iter().map(|&k| quad[k]));
+                }
+            }
+        }
+    }
+    mesh.index_count = mesh.indices.len() as i32;
+    stats.triangles = mesh.indices.len() / 3;
+    mesh.recompute_normals();
+    Ok((mesh, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::marching_cubes;
+
+    /// Signed distance to a sphere of `radius` around the middle of an
+    /// `n + 1` point lattice
+    fn sphere(n: usize, radius: f64) -> Vec<Vec<Vec<f64>>> {
+        let c = n as f64 / 2.0;
+        (0..=n).map(|i| (0..=n).map(|j| (0..=n).map(|k| {
+            ((i as f64 - c).powi(2) + (j as f64 - c).powi(2) + (k as f64 - c).powi(2)).sqrt() - radius
+        }).collect()).collect()).collect()
+    }
+
+    #[test]
+    fn sphere_matches_marching_cubes() {
+        let (scale, radius) = (0.5, 6.0);
+        let points = sphere(16, radius);
+        let opts = OctreeOptions { max_error: 0.05, min_size: 1, max_size: 8 };
+        let (octree, stats) = octree_extract((0, 0, 0), 16, scale, &points, 0.0, &opts).unwrap();
+        let mc = marching_cubes((0, 0, 0), scale, &points, 0.0).weld();
+
+        // Leaves of several sizes meet without cracks
+        assert!(stats.leaves < 16 * 16 * 16 && stats.leaves > 8, "{:?}", stats);
+        let report = octree.validate();
+        assert!(report.is_watertight(), "{:?}", report);
+
+        let (a, b) = (octree.volume(), mc.volume());
+        assert!((a / b - 1.0).abs() < 0.03, "volume {} against {}", a, b);
+        let (omin, omax) = octree.bounds().unwrap();
+        let (mmin, mmax) = mc.bounds().unwrap();
+        assert!(glm::max2(&(omin - mmin).abs(), &(omax - mmax).abs()).max() < scale);
+        let centre = glm::Vec3::repeat(8.0 * scale);
+        for i in 0..octree.vertex_count() as u32 {
+            let r = glm::distance(&octree.position(i), &centre) / scale;
+            assert!((r - radius as f32).abs() < 0.25, "vertex {} at radius {}", i, r);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_regions() {
+        let points = sphere(16, 6.0);
+        let opts = OctreeOptions::default();
+        assert!(octree_extract((0, 0, 0), 12, 1.0, &points, 0.0, &opts).is_err());
+        assert!(octree_extract((1, 0, 0), 16, 1.0, &points, 0.0, &opts).is_err());
+        assert!(octree_extract((8, 8, 8), 8, 1.0, &points, 0.0, &opts).is_ok());
+    }
+
+    #[test]
+    fn larger_min_size_keeps_smooth_surfaces_but_loses_small_features() {
+        let opts = OctreeOptions { max_error: 0.05, min_size: 2, max_size: 8 };
+        let (mesh, stats) = octree_extract((0, 0, 0), 16, 1.0, &sphere(16, 6.0), 0.0, &opts).unwrap();
+        assert!(mesh.validate().is_watertight());
+        assert!(stats.leaves <= 8 * 8 * 8);
+
+        // One sample inside, at an odd lattice point no leaf of 2 cells has
+        // as a corner
+        let mut points = vec![vec![vec![1.0; 17]; 17]; 17];
+        points[7][7][7] = -1.0;
+        let fine = OctreeOptions { min_size: 1, ..opts };
+        let (mesh, _) = octree_extract((0, 0, 0), 16, 1.0, &points, 0.0, &fine).unwrap();
+        assert!(!mesh.indices.is_empty() && mesh.validate().is_watertight());
+        let (mesh, _) = octree_extract((0, 0, 0), 16, 1.0, &points, 0.0, &opts).unwrap();
+        assert!(mesh.indices.is_empty());
+    }
+}
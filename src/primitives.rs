@@ -0,0 +1,409 @@
+// Generators for simple shapes, for gizmos, markers and debug drawing
+
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+
+use crate::lines::Lines;
+use crate::mc::Mesh;
+use crate::util::{from_array_of_vec2, from_array_of_vec3};
+
+/// Point at angle `phi` around the y axis, starting at +z and turning
+/// towards +x, so that u grows to the right seen from outside
+fn around_y(phi: f32, radius: f32, y: f32) -> glm::Vec3 {
+    glm::vec3(radius * phi.sin(), y, radius * phi.cos())
+}
+
+/// Angle of step `i` of `n` around a circle, the same for the first and
+/// last vertex of a seam so they weld
+fn angle(i: usize, n: usize) -> f32 {
+    (i % n) as f32 / n as f32 * TAU
+}
+
+fn build(positions: Vec<glm::Vec3>, normals: Vec<glm::Vec3>, uvs: Vec<glm::Vec2>, indices: Vec<u32>) -> Mesh {
+    Mesh {
+        vertices: from_array_of_vec3(positions),
+        normals: from_array_of_vec3(normals),
+        texture_coordinates: from_array_of_vec2(uvs),
+        index_count: indices.len() as i32,
+        indices,
+        ..Default::default()
+    }
+}
+
+/// Disc of `radius` in the plane y, facing up or down, with UVs mapped
+/// across it as seen from the side it faces
+fn disc(radius: f32, y: f32, segments: usize, up: bool) -> Mesh {
+    let normal = if up { glm::Vec3::y() } else { -glm::Vec3::y() };
+    let mut positions = vec![glm::vec3(0.0, y, 0.0)];
+    positions.extend((0..segments).map(|s| around_y(angle(s, segments), radius, y)));
+    let uvs = positions.iter()
+        .map(|p| glm::vec2(0.5 + p.x / (2.0 * radius), 0.5 + (if up { -p.z } else { p.z }) / (2.0 * radius)))
+        .collect();
+    let indices = (0..segments as u32).flat_map(|s| {
+        let (a, b) = (s + 1, (s + 1) % segments as u32 + 1);
+        if up { [0, a, b] } else { [0, b, a] }
+    }).collect();
+    build(positions, vec![normal; segments + 1], uvs, indices)
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Append another mesh's triangles. Attributes missing from either
+    /// mesh are dropped
+    pub fn append(&mut self, other: &Mesh) {
+        let (n, m) = (self.vertex_count(), other.vertex_count());
+        let offset = n as u32;
+        // Kept when both have the same number of components per vertex
+        let join = |a: &mut Vec<f32>, b: &Vec<f32>| {
+            if n == 0 || m == 0 || a.len() * m == b.len() * n {
+                a.extend_from_slice(b);
+            } else {
+                a.clear();
+            }
+        };
+        join(&mut self.vertices, &other.vertices);
+        join(&mut self.normals, &other.normals);
+        join(&mut self.texture_coordinates, &other.texture_coordinates);
+        join(&mut self.colors, &other.colors);
+        join(&mut self.occlusion, &other.occlusion);
+        self.indices.extend(other.indices.iter().map(|i| i + offset));
+        self.index_count = self.indices.len() as i32;
+    }
+
+    /// Transform positions by `m`, and normals by its inverse transpose
+    pub fn transform(&mut self, m: &glm::Mat4) {
+        let normal_matrix = glm::transpose(&glm::inverse(&glm::mat4_to_mat3(m)));
+        for p in self.vertices.chunks_exact_mut(3) {
+            let q = m * glm::vec4(p[0], p[1], p[2], 1.0);
+            p.copy_from_slice(&[q.x / q.w, q.y / q.w, q.z / q.w]);
+        }
+        for n in self.normals.chunks_exact_mut(3) {
+            let q = glm::normalize(&(normal_matrix * glm::vec3(n[0], n[1], n[2])));
+            n.copy_from_slice(q.as_slice());
+        }
+    }
+
+    /// Set every vertex to one RGBA colour
+    pub fn set_color(&mut self, color: glm::Vec4) {
+        self.colors = (0..self.vertex_count()).flat_map(|_| [color.x, color.y, color.z, color.w]).collect();
+    }
+
+    /// Sphere around the origin with poles on the y axis, `segments` around
+    /// and `rings` from pole to pole. The UV seam is duplicated
+    pub fn uv_sphere(radius: f32, segments: usize, rings: usize) -> Mesh {
+        let (segments, rings) = (segments.max(3), rings.max(2));
+        let (mut positions, mut normals, mut uvs) = (Vec::new(), Vec::new(), Vec::new());
+        for r in 0..=rings {
+            let theta = r as f32 / rings as f32 * PI;
+            // Exact at the poles, so welding closes them
+            let (sin, cos) = match r {
+                0 => (0.0, 1.0),
+                r if r == rings => (0.0, -1.0),
+                _ => theta.sin_cos(),
+            };
+            for s in 0..=segments {
+                let u = s as f32 / segments as f32;
+                let n = around_y(angle(s, segments), sin, cos);
+                positions.push(n * radius);
+                normals.push(n);
+                uvs.push(glm::vec2(u, 1.0 - r as f32 / rings as f32));
+            }
+        }
+        let row = segments as u32 + 1;
+        let mut indices = Vec::new();
+        for r in 0..rings as u32 {
+            for s in 0..segments as u32 {
+                let (a, b, c, d) = (r * row + s, (r + 1) * row + s, (r + 1) * row + s + 1, r * row + s + 1);
+                // The triangles touching a pole would be degenerate
+                if r + 1 < rings as u32 { indices.extend_from_slice(&[a, b, c]) }
+                if r > 0 { indices.extend_from_slice(&[a, c, d]) }
+            }
+        }
+        // Drops the pole vertex past the last segment, which no triangle uses
+        let mut mesh = build(positions, normals, uvs, indices);
+        mesh.compact();
+        mesh
+    }
+
+    /// Sphere from an icosahedron with every face split into four
+    /// `subdivisions` times, for evenly sized triangles. UVs are spherical as
+    /// for `uv_sphere`, with vertices duplicated along the seam and at poles
+    pub fn icosphere(radius: f32, subdivisions: usize) -> Mesh {
+        let t = (1.0 + 5f32.sqrt()) / 2.0;
+        let mut positions = [
+            [-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+            [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+            [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0],
+        ].map(|p: [f32; 3]| glm::normalize(&glm::make_vec3(&p))).to_vec();
+        let mut faces: Vec<[u32; 3]> = vec![
+            [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+            [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+            [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+            [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+        ];
+        for _ in 0..subdivisions {
+            let mut midpoints = HashMap::new();
+            let mut midpoint = |a: u32, b: u32, positions: &mut Vec<glm::Vec3>| *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                positions.push(glm::normalize(&(positions[a as usize] + positions[b as usize])));
+                positions.len() as u32 - 1
+            });
+            faces = faces.iter().flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b, &mut positions), midpoint(b, c, &mut positions), midpoint(c, a, &mut positions));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            }).collect();
+        }
+
+        let uv = |n: &glm::Vec3| glm::vec2(
+            n.x.atan2(n.z).rem_euclid(TAU) / TAU,
+            1.0 - n.y.clamp(-1.0, 1.0).acos() / PI,
+        );
+        let mut uvs = positions.iter().map(uv).collect::<Vec<_>>();
+        // Faces across the seam take copies of their vertices with u past 1,
+        // and faces at a pole a copy of it centred between the others
+        let pole = |p: &glm::Vec3| p.y.abs() > 1.0 - 1e-6;
+        let mut wrapped = HashMap::new();
+        for f in faces.iter_mut() {
+            let us = f.iter().filter(|&&v| !pole(&positions[v as usize])).map(|&v| uvs[v as usize].x);
+            let (lo, hi) = us.fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), u| (lo.min(u), hi.max(u)));
+            let wrap = hi - lo > 0.5;
+            for k in 0..3 {
+                let v = f[k] as usize;
+                if pole(&positions[v]) {
+                    let (a, b) = (uvs[f[(k + 1) % 3] as usize].x, uvs[f[(k + 2) % 3] as usize].x);
+                    let (a, b) = if (a - b).abs() > 0.5 { (a.max(b), a.min(b) + 1.0) } else { (a, b) };
+                    positions.push(positions[v]);
+                    uvs.push(glm::vec2(0.5 * (a + b), uvs[v].y));
+                    f[k] = positions.len() as u32 - 1;
+                } else if wrap && uvs[v].x < 0.5 {
+                    f[k] = *wrapped.entry(v).or_insert_with(|| {
+                        positions.push(positions[v]);
+                        uvs.push(uvs[v] + glm::vec2(1.0, 0.0));
+                        positions.len() as u32 - 1
+                    });
+                }
+            }
+        }
+        let normals = positions.clone();
+        positions.iter_mut().for_each(|p| *p *= radius);
+        // The original poles are no longer used
+        let mut mesh = build(positions, normals, uvs, faces.concat());
+        mesh.compact();
+        mesh
+    }
+
+    /// Flat grid in the xz plane around the origin, facing +y, with
+    /// `divisions` quads along x and z. UVs span it once, with v towards -z
+    pub fn plane(size: glm::Vec2, divisions: (usize, usize)) -> Mesh {
+        let (nx, nz) = (divisions.0.max(1), divisions.1.max(1));
+        let (mut positions, mut uvs) = (Vec::new(), Vec::new());
+        for i in 0..=nx {
+            for j in 0..=nz {
+                let (u, w) = (i as f32 / nx as f32, j as f32 / nz as f32);
+                positions.push(glm::vec3((u - 0.5) * size.x, 0.0, (w - 0.5) * size.y));
+                uvs.push(glm::vec2(u, 1.0 - w));
+            }
+        }
+        let row = nz as u32 + 1;
+        let indices = (0..nx as u32).flat_map(|i| (0..nz as u32).flat_map(move |j| {
+            let (a, b, c, d) = (i * row + j, i * row + j + 1, (i + 1) * row + j + 1, (i + 1) * row + j);
+            [a, b, c, a, c, d]
+        })).collect();
+        let n = positions.len();
+        build(positions, vec![glm::Vec3::y(); n], uvs, indices)
+    }
+
+    /// Cylinder around the y axis, centred on the origin. Caps have their
+    /// own vertices, so the rim stays sharp
+    pub fn cylinder(radius: f32, height: f32, segments: usize, capped: bool) -> Mesh {
+        let segments = segments.max(3);
+        let (mut positions, mut normals, mut uvs) = (Vec::new(), Vec::new(), Vec::new());
+        for (y, v) in [(0.5 * height, 1.0), (-0.5 * height, 0.0)] {
+            for s in 0..=segments {
+                let u = s as f32 / segments as f32;
+                positions.push(around_y(angle(s, segments), radius, y));
+                normals.push(around_y(angle(s, segments), 1.0, 0.0));
+                uvs.push(glm::vec2(u, v));
+            }
+        }
+        let row = segments as u32 + 1;
+        let indices = (0..segments as u32).flat_map(|s| [s, row + s, row + s + 1, s, row + s + 1, s + 1]).collect();
+        let mut mesh = build(positions, normals, uvs, indices);
+        if capped {
+            mesh.append(&disc(radius, 0.5 * height, segments, true));
+            mesh.append(&disc(radius, -0.5 * height, segments, false));
+        }
+        mesh
+    }
+
+    /// Cone around the y axis with its base on the origin and its apex at
+    /// `height`. The apex is split per segment so normals follow the slope
+    pub fn cone(radius: f32, height: f32, segments: usize, capped: bool) -> Mesh {
+        let segments = segments.max(3);
+        let slope = |phi: f32| glm::normalize(&around_y(phi, height, radius));
+        let (mut positions, mut normals, mut uvs) = (Vec::new(), Vec::new(), Vec::new());
+        for s in 0..=segments {
+            let u = s as f32 / segments as f32;
+            positions.push(around_y(angle(s, segments), radius, 0.0));
+            normals.push(slope(angle(s, segments)));
+            uvs.push(glm::vec2(u, 0.0));
+        }
+        for s in 0..segments {
+            let u = (s as f32 + 0.5) / segments as f32;
+            positions.push(glm::vec3(0.0, height, 0.0));
+            normals.push(slope(u * TAU));
+            uvs.push(glm::vec2(u, 1.0));
+        }
+        let apex = segments as u32 + 1;
+        let indices = (0..segments as u32).flat_map(|s| [apex + s, s, s + 1]).collect();
+        let mut mesh = build(positions, normals, uvs, indices);
+        if capped {
+            mesh.append(&disc(radius, 0.0, segments, false));
+        }
+        mesh
+    }
+
+    /// Torus around the y axis, with `segments` around the ring and `sides`
+    /// around the tube
+    pub fn torus(major_radius: f32, minor_radius: f32, segments: usize, sides: usize) -> Mesh {
+        let (segments, sides) = (segments.max(3), sides.max(3));
+        let (mut positions, mut normals, mut uvs) = (Vec::new(), Vec::new(), Vec::new());
+        for s in 0..=segments {
+            let u = s as f32 / segments as f32;
+            for t in 0..=sides {
+                let v = t as f32 / sides as f32;
+                let (sin, cos) = angle(t, sides).sin_cos();
+                let n = around_y(angle(s, segments), cos, sin);
+                positions.push(around_y(angle(s, segments), major_radius + minor_radius * cos, minor_radius * sin));
+                normals.push(n);
+                uvs.push(glm::vec2(u, v));
+            }
+        }
+        let row = sides as u32 + 1;
+        let indices = (0..segments as u32).flat_map(|s| (0..sides as u32).flat_map(move |t| {
+            let (a, b, c, d) = (s * row + t, (s + 1) * row + t, (s + 1) * row + t + 1, s * row + t + 1);
+            [a, b, c, a, c, d]
+        })).collect();
+        build(positions, normals, uvs, indices)
+    }
+
+    /// Arrow from the origin along +y, a capped cylinder shaft with a cone
+    /// head at the tip
+    pub fn arrow(length: f32, shaft_radius: f32, head_radius: f32, head_length: f32, segments: usize) -> Mesh {
+        let head_length = head_length.min(length);
+        let shaft = length - head_length;
+        let mut mesh = Mesh::cylinder(shaft_radius, shaft, segments, true);
+        mesh.transform(&glm::translation(&glm::vec3(0.0, 0.5 * shaft, 0.0)));
+        let mut head = Mesh::cone(head_radius, head_length, segments, true);
+        head.transform(&glm::translation(&glm::vec3(0.0, shaft, 0.0)));
+        mesh.append(&head);
+        mesh
+    }
+
+    /// Arrows along +x, +y and +z, coloured red, green and blue
+    pub fn axis_gizmo(length: f32) -> Mesh {
+        let mut mesh = Mesh::new();
+        let turns = [
+            glm::rotation(-0.5 * PI, &glm::Vec3::z()),
+            glm::identity(),
+            glm::rotation(0.5 * PI, &glm::Vec3::x()),
+        ];
+        for (axis, turn) in turns.iter().enumerate() {
+            let mut arrow = Mesh::arrow(length, 0.02 * length, 0.06 * length, 0.2 * length, 12);
+            arrow.transform(turn);
+            let mut color = glm::vec4(0.0, 0.0, 0.0, 1.0);
+            color[axis] = 1.0;
+            arrow.set_color(color);
+            mesh.append(&arrow);
+        }
+        mesh
+    }
+}
+
+#[allow(unused)]
+impl Lines {
+    /// The 12 edges of an axis aligned box
+    pub fn line_box(min: glm::Vec3, max: glm::Vec3) -> Lines {
+        // Corner c takes max on axis x, y, z where bit 4, 2, 1 of c is set
+        let vertices = (0..8).flat_map(|c| [
+            if c & 4 != 0 { max.x } else { min.x },
+            if c & 2 != 0 { max.y } else { min.y },
+            if c & 1 != 0 { max.z } else { min.z },
+        ]).collect();
+        let indices = (0..8u32)
+            .flat_map(|c| [4, 2, 1].into_iter().filter(move |bit| c & bit == 0).flat_map(move |bit| [c, c | bit]))
+            .collect();
+        Lines { vertices, indices, ..Default::default() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shapes() -> Vec<(&'static str, Mesh)> {
+        vec![
+            ("uv_sphere", Mesh::uv_sphere(1.5, 16, 9)),
+            ("icosphere", Mesh::icosphere(1.5, 2)),
+            ("cylinder", Mesh::cylinder(1.0, 2.0, 12, true)),
+            ("cone", Mesh::cone(1.0, 2.0, 12, true)),
+            ("torus", Mesh::torus(2.0, 0.5, 16, 8)),
+            ("arrow", Mesh::arrow(3.0, 0.2, 0.5, 1.0, 12)),
+        ]
+    }
+
+    #[test]
+    fn normals_agree_with_winding() {
+        let mut all = shapes();
+        all.push(("plane", Mesh::plane(glm::vec2(2.0, 3.0), (3, 2))));
+        all.push(("open cylinder", Mesh::cylinder(1.0, 2.0, 12, false)));
+        for (name, mesh) in all {
+            assert_eq!(mesh.validate_attributes(), Ok(mesh.vertex_count()), "{}", name);
+            let normals = mesh.vertex_normals();
+            for t in mesh.indices.chunks_exact(3) {
+                let (a, b, c) = (mesh.position(t[0]), mesh.position(t[1]), mesh.position(t[2]));
+                let face = glm::cross(&(b - a), &(c - a));
+                if glm::length(&face) < 1e-6 { continue }
+                let face = glm::normalize(&face);
+                for &v in t {
+                    let n = normals[v as usize];
+                    assert!((glm::length(&n) - 1.0).abs() < 1e-4, "{}: normal {:?}", name, n);
+                    assert!(glm::dot(&face, &n) > 0.1, "{}: face {:?} against vertex normal {:?}", name, face, n);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn uvs_are_in_range_and_not_mirrored() {
+        let mut all = shapes();
+        all.push(("plane", Mesh::plane(glm::vec2(2.0, 3.0), (3, 2))));
+        for (name, mesh) in all {
+            let uv = |i: u32| glm::vec2(mesh.texture_coordinates[i as usize * 2], mesh.texture_coordinates[i as usize * 2 + 1]);
+            // The icosphere's seam faces take u past 1 rather than share
+            // the seam's vertices
+            let max_u = if name == "icosphere" { 1.5 } else { 1.0 };
+            for i in 0..mesh.vertex_count() as u32 {
+                let p = uv(i);
+                assert!((0.0..=max_u).contains(&p.x) && (0.0..=1.0).contains(&p.y), "{}: uv {:?}", name, p);
+            }
+            for t in mesh.indices.chunks_exact(3) {
+                // Counter-clockwise from outside is counter-clockwise in UV
+                let (a, b, c) = (uv(t[0]), uv(t[1]), uv(t[2]));
+                let area = (b - a).perp(&(c - a));
+                assert!(area >= -1e-6, "{}: triangle {:?} has uv area {}", name, t, area);
+            }
+        }
+    }
+
+    #[test]
+    fn closed_shapes_weld_watertight() {
+        for (name, mesh) in shapes() {
+            let welded = mesh.weld();
+            let report = welded.validate();
+            assert!(report.is_watertight(), "{}: {:?}", name, report);
+            assert!(welded.volume() > 0.0, "{}", name);
+        }
+        let report = Mesh::cylinder(1.0, 2.0, 12, false).weld().validate();
+        assert!(!report.is_closed());
+    }
+}
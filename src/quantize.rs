@@ -0,0 +1,226 @@
+// Packed vertex format with quantised positions and octahedral normals
+
+use crate::mc::Mesh;
+
+/// Largest quantised position coordinate
+const POSITION_MAX: f32 = u16::MAX as f32;
+/// Largest octahedral coordinate, as snorm16
+const NORMAL_MAX: f32 = i16::MAX as f32;
+
+/// Upper bound on the angle in radians between a unit normal and its
+/// decoded octahedral encoding. Rounding both coordinates by half a step
+/// moves the point on the octahedron by at most √6 half steps, and the
+/// octahedron is at least 1/√3 from the centre, so the angle is under √18
+/// half steps, about 6.5e-5 or 0.004°, with some margin for f32 rounding
+#[allow(unused)]
+pub const NORMAL_ERROR: f32 = 7e-5;
+
+/// Index buffer in the narrowest type that fits the vertex count
+#[allow(unused)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum PackedIndices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+/// Positions, normals and indices of a mesh in 10 bytes per vertex instead
+/// of 24, plus 2 per index where fewer than 65536 vertices allow it
+#[allow(unused)]
+#[derive(Clone, Debug)]
+pub struct PackedMesh {
+    /// Corner of the box positions are quantised over
+    pub origin: glm::Vec3,
+    /// Size of that box along each axis
+    pub extent: glm::Vec3,
+    /// Three per vertex, 0 at `origin` and 65535 at `origin + extent`
+    pub positions: Vec<u16>,
+    /// Two per vertex, see `encode_octahedral`
+    pub normals: Vec<i16>,
+    pub indices: PackedIndices,
+}
+
+/// Sign that takes zero as positive, so the octahedron folds without gaps
+fn sign_not_zero(x: f32) -> f32 {
+    if x >= 0.0 { 1.0 } else { -1.0 }
+}
+
+/// Map the octahedron's lower half onto the corners of its upper half's
+/// square. The map is its own inverse
+fn fold(x: f32, y: f32) -> (f32, f32) {
+    ((1.0 - y.abs()) * sign_not_zero(x), (1.0 - x.abs()) * sign_not_zero(y))
+}
+
+/// Unit vector projected onto the octahedron |x| + |y| + |z| = 1 and
+/// unfolded into a square, as two snorm16 values
+pub fn encode_octahedral(n: &glm::Vec3) -> [i16; 2] {
+    let l1 = n.x.abs() + n.y.abs() + n.z.abs();
+    if l1 == 0.0 { return [0, 0] }
+    let (x, y) = (n.x / l1, n.y / l1);
+    let (x, y) = if n.z < 0.0 { fold(x, y) } else { (x, y) };
+    [x, y].map(|c| (c.clamp(-1.0, 1.0) * NORMAL_MAX).round() as i16)
+}
+
+pub fn decode_octahedral(e: [i16; 2]) -> glm::Vec3 {
+    let (x, y) = (e[0] as f32 / NORMAL_MAX, e[1] as f32 / NORMAL_MAX);
+    let z = 1.0 - x.abs() - y.abs();
+    let (x, y) = if z < 0.0 { fold(x, y) } else { (x, y) };
+    glm::normalize(&glm::vec3(x, y, z))
+}
+
+/// Position as u16 steps across the box from `origin` of size `extent`,
+/// clamped to the box
+pub fn quantize_position(p: &glm::Vec3, origin: &glm::Vec3, extent: &glm::Vec3) -> [u16; 3] {
+    [0, 1, 2].map(|a| {
+        if extent[a] <= 0.0 { return 0 }
+        ((p[a] - origin[a]) / extent[a] * POSITION_MAX).round().clamp(0.0, POSITION_MAX) as u16
+    })
+}
+
+pub fn dequantize_position(q: [u16; 3], origin: &glm::Vec3, extent: &glm::Vec3) -> glm::Vec3 {
+    origin + glm::vec3(q[0] as f32, q[1] as f32, q[2] as f32).component_mul(extent) / POSITION_MAX
+}
+
+#[allow(unused)]
+impl PackedMesh {
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len() / 3
+    }
+
+    /// Largest difference per axis between a position inside the box and
+    /// its decoded quantisation, half a step
+    pub fn position_error(&self) -> glm::Vec3 {
+        self.extent / (2.0 * POSITION_MAX)
+    }
+
+    /// Bytes used by the vertex and index data
+    pub fn byte_size(&self) -> usize {
+        let indices = match &self.indices {
+            PackedIndices::U16(i) => i.len() * 2,
+            PackedIndices::U32(i) => i.len() * 4,
+        };
+        self.positions.len() * 2 + self.normals.len() * 2 + indices
+    }
+
+    /// Decoded mesh, without texture coordinates or colours
+    pub fn unpack(&self) -> Mesh {
+        let vertices = self.positions.chunks_exact(3)
+            .flat_map(|q| dequantize_position([q[0], q[1], q[2]], &self.origin, &self.extent).as_slice().to_vec())
+            .collect();
+        let normals = self.normals.chunks_exact(2)
+            .flat_map(|e| decode_octahedral([e[0], e[1]]).as_slice().to_vec())
+            .collect();
+        let indices: Vec<u32> = match &self.indices {
+            PackedIndices::U16(i) => i.iter().map(|&i| i as u32).collect(),
+            PackedIndices::U32(i) => i.clone(),
+        };
+        Mesh { vertices, normals, index_count: indices.len() as i32, indices, ..Default::default() }
+    }
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Pack positions, normals and indices, with positions quantised over
+    /// the box from `origin` of size `extent`, e.g. `c0 * scale` and
+    /// `16 * scale` for a chunk from `marching_cubes`. Positions outside the
+    /// box are clamped. Normals are taken as by `vertex_normals`. Packing
+    /// does not share vertices, so weld first where smooth shading is wanted
+    pub fn pack(&self, origin: glm::Vec3, extent: glm::Vec3) -> PackedMesh {
+        let positions = (0..self.vertex_count() as u32)
+            .flat_map(|i| quantize_position(&self.position(i), &origin, &extent))
+            .collect();
+        let normals = self.vertex_normals().iter().flat_map(encode_octahedral).collect();
+        let indices = if self.vertex_count() <= u16::MAX as usize + 1 {
+            PackedIndices::U16(self.indices.iter().map(|&i| i as u16).collect())
+        } else {
+            PackedIndices::U32(self.indices.clone())
+        };
+        PackedMesh { origin, extent, positions, normals, indices }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use crate::mc::marching_cubes;
+
+    /// Angle between unit vectors, accurate for small angles unlike acos
+    fn angle(a: &glm::Vec3, b: &glm::Vec3) -> f32 {
+        2.0 * (glm::distance(a, b) / 2.0).min(1.0).asin()
+    }
+
+    #[test]
+    fn normals_round_trip_within_bound() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(48);
+        let mut normals = Vec::new();
+        // Axes, octant diagonals and points on the fold at z = 0
+        for a in 0..3 {
+            for s in [-1.0, 1.0] {
+                let mut n = glm::Vec3::zeros();
+                n[a] = s;
+                normals.push(n);
+            }
+        }
+        for i in 0..8 {
+            normals.push(glm::vec3(1.0 - 2.0 * (i & 1) as f32, 1.0 - (i & 2) as f32, 1.0 - (i & 4) as f32 / 2.0));
+            normals.push(glm::vec3(1.0 - 2.0 * (i & 1) as f32, 1e-3 - (i & 2) as f32, 0.0));
+        }
+        while normals.len() < 200_000 {
+            let n = glm::vec3(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            if (1e-6..=1.0).contains(&glm::length2(&n)) {
+                normals.push(n);
+            }
+        }
+        for n in normals.iter().map(glm::normalize) {
+            let d = decode_octahedral(encode_octahedral(&n));
+            assert!(angle(&n, &d) <= NORMAL_ERROR, "{:?} decoded as {:?}", n, d);
+        }
+    }
+
+    /// Signed distance to a sphere of `radius` around the middle of an
+    /// `n + 1` point lattice
+    fn sphere(n: usize, radius: f64) -> Vec<Vec<Vec<f64>>> {
+        let c = n as f64 / 2.0;
+        (0..=n).map(|i| (0..=n).map(|j| (0..=n).map(|k| {
+            ((i as f64 - c).powi(2) + (j as f64 - c).powi(2) + (k as f64 - c).powi(2)).sqrt() - radius
+        }).collect()).collect()).collect()
+    }
+
+    #[test]
+    fn pack_round_trips_within_position_error() {
+        let scale = 0.5;
+        let mesh = marching_cubes((0, 0, 0), scale, &sphere(16, 6.0), 0.0).weld();
+        let (origin, extent) = (glm::Vec3::zeros(), glm::Vec3::repeat(16.0 * scale));
+        let packed = mesh.pack(origin, extent);
+        assert!(matches!(packed.indices, PackedIndices::U16(_)));
+        assert!(packed.byte_size() < mesh.vertices.len() * 4 + mesh.normals.len() * 4 + mesh.indices.len() * 4);
+
+        let unpacked = packed.unpack();
+        assert_eq!(unpacked.indices, mesh.indices);
+        assert_eq!(unpacked.vertex_count(), mesh.vertex_count());
+        let error = packed.position_error();
+        let normals = mesh.vertex_normals();
+        for i in 0..mesh.vertex_count() as u32 {
+            let d = (unpacked.position(i) - mesh.position(i)).abs();
+            // Allow for f32 rounding in the (de)quantisation
+            assert!(d.iter().zip(error.iter()).all(|(d, e)| *d <= e * 1.001), "vertex {} off by {:?}", i, d);
+            let n = glm::vec3(unpacked.normals[i as usize * 3], unpacked.normals[i as usize * 3 + 1], unpacked.normals[i as usize * 3 + 2]);
+            assert!(angle(&n, &glm::normalize(&normals[i as usize])) <= NORMAL_ERROR);
+        }
+    }
+
+    #[test]
+    fn index_width_follows_vertex_count() {
+        let mesh = |n: usize| Mesh {
+            vertices: vec![0.0; n * 3],
+            indices: vec![0, 1, n as u32 - 1],
+            index_count: 3,
+            ..Default::default()
+        };
+        let (origin, extent) = (glm::Vec3::zeros(), glm::Vec3::repeat(1.0));
+        assert_eq!(mesh(65536).pack(origin, extent).indices, PackedIndices::U16(vec![0, 1, 65535]));
+        let packed = mesh(65537).pack(origin, extent);
+        assert_eq!(packed.indices, PackedIndices::U32(vec![0, 1, 65536]));
+        assert_eq!(packed.unpack().indices, vec![0, 1, 65536]);
+    }
+}
@@ -0,0 +1,290 @@
+// Software rasterizer for rendering meshes without a GPU
+
+use std::path::Path;
+
+use crate::colorize::COLOR_STRIDE;
+use crate::mc::Mesh;
+use crate::texcoord::TRIPLANAR_STRIDE;
+
+/// Colour of back faces, as in shaders/simple.frag
+const BACK_FACE_COLOR: [f32; 3] = [0.0, 0.0, 0.8];
+/// Ambient term, as in shaders/simple.frag
+const AMBIENT: f32 = 0.2;
+/// How much the dark squares of the checker texture darken the colour, as in
+/// shaders/simple.frag
+const CHECKER_DARKEN: f32 = 0.3;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub view: glm::Mat4,
+    pub projection: glm::Mat4,
+}
+
+#[allow(unused)]
+impl Camera {
+    pub fn look_at(eye: glm::Vec3, target: glm::Vec3, up: glm::Vec3, fovy: f32, aspect: f32, near: f32, far: f32) -> Self {
+        Camera {
+            view: glm::look_at(&eye, &target, &up),
+            projection: glm::perspective(aspect, fovy, near, far),
+        }
+    }
+
+    /// Camera looking along `direction` at the box from `min` to `max`,
+    /// placed so the whole box is in view
+    pub fn framing(min: glm::Vec3, max: glm::Vec3, direction: glm::Vec3, fovy: f32, aspect: f32) -> Self {
+        let center = (min + max) * 0.5;
+        let radius = (glm::distance(&min, &max) * 0.5).max(f32::EPSILON);
+        let fov = fovy.min(2.0 * (aspect * (fovy * 0.5).tan()).atan());
+        let distance = radius / (fov * 0.5).sin();
+        let dir = glm::normalize(&direction);
+        let up = if dir.y.abs() > 0.99 { glm::Vec3::z() } else { glm::Vec3::y() };
+        Camera::look_at(
+            center - dir * distance, center, up, fovy, aspect,
+            (distance - radius) * 0.5, distance + radius * 2.0,
+        )
+    }
+
+    pub fn view_projection(&self) -> glm::Mat4 {
+        self.projection * self.view
+    }
+}
+
+/// Vertex after the vertex stage: clip space position, world normal and
+/// the attributes interpolated for the fragment stage
+#[derive(Clone, Copy)]
+struct ClipVertex {
+    position: glm::Vec4,
+    normal: glm::Vec3,
+    color: glm::Vec4,
+    occlusion: f32,
+    /// Triplanar weights and the UVs of the three projections
+    tex_weights: glm::Vec3,
+    tex_uv: [glm::Vec2; 3],
+    /// Plain texture coordinates
+    tex_coord: glm::Vec2,
+}
+
+impl ClipVertex {
+    /// Weighted sum of every attribute of the vertices
+    fn mix<const N: usize>(v: [(&ClipVertex, f32); N]) -> ClipVertex {
+        let mut out = ClipVertex {
+            position: glm::Vec4::zeros(),
+            normal: glm::Vec3::zeros(),
+            color: glm::Vec4::zeros(),
+            occlusion: 0.0,
+            tex_weights: glm::Vec3::zeros(),
+            tex_uv: [glm::Vec2::zeros(); 3],
+            tex_coord: glm::Vec2::zeros(),
+        };
+        for (x, w) in v {
+            out.position += x.position * w;
+            out.normal += x.normal * w;
+            out.color += x.color * w;
+            out.occlusion += x.occlusion * w;
+            out.tex_weights += x.tex_weights * w;
+            for k in 0..3 {
+                out.tex_uv[k] += x.tex_uv[k] * w;
+            }
+            out.tex_coord += x.tex_coord * w;
+        }
+        out
+    }
+}
+
+/// Procedural stand-in for a texture, 1 on dark squares, as in
+/// shaders/simple.frag
+fn checker(uv: &glm::Vec2) -> f32 {
+    (uv.x.floor() + uv.y.floor()).rem_euclid(2.0)
+}
+
+/// Clip a triangle against the near plane, z >= -w
+fn clip_near(t: [ClipVertex; 3]) -> Vec<ClipVertex> {
+    let d = |v: &ClipVertex| v.position.z + v.position.w;
+    let mut out = Vec::with_capacity(4);
+    for i in 0..3 {
+        let (a, b) = (t[i], t[(i + 1) % 3]);
+        let (da, db) = (d(&a), d(&b));
+        if da >= 0.0 { out.push(a) }
+        if (da >= 0.0) != (db >= 0.0) {
+            let s = da / (da - db);
+            out.push(ClipVertex::mix([(&a, 1.0 - s), (&b, s)]));
+        }
+    }
+    out
+}
+
+/// Colour and depth buffers. Pixel (0, 0) is the top left
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    color: Vec<glm::Vec4>,
+    depth: Vec<f32>,
+}
+
+#[allow(unused)]
+impl Framebuffer {
+    pub fn new(width: usize, height: usize, background: glm::Vec4) -> Self {
+        Framebuffer {
+            width,
+            height,
+            color: vec![background; width * height],
+            depth: vec![f32::INFINITY; width * height],
+        }
+    }
+
+    pub fn clear(&mut self, background: glm::Vec4) {
+        self.color.fill(background);
+        self.depth.fill(f32::INFINITY);
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> glm::Vec4 {
+        self.color[y * self.width + x]
+    }
+
+    /// Draw the mesh with depth testing and the Lambert plus ambient
+    /// shading of shaders/simple.frag. Faces are front facing when counter-
+    /// clockwise on screen, as in OpenGL. Meshes without per-vertex normals
+    /// are shaded with face normals. Where present, vertex colours replace
+    /// `color`, triplanar or 2 component texture coordinates apply the
+    /// shader's checker texture and baked occlusion darkens the ambient term
+    pub fn draw_mesh(&mut self, mesh: &Mesh, model: &glm::Mat4, camera: &Camera, color: glm::Vec4) {
+        let mvp = camera.view_projection() * model;
+        let normal_matrix = glm::transpose(&glm::inverse(&glm::mat4_to_mat3(model)));
+        let light_dir = glm::normalize(&glm::vec3(1.0, 2.0, 1.0));
+        let smooth = mesh.normals.len() == mesh.vertices.len();
+        let occluded = mesh.occlusion.len() == mesh.vertex_count();
+        let colored = mesh.colors.len() == mesh.vertex_count() * COLOR_STRIDE;
+        let triplanar = mesh.texture_coordinates.len() == mesh.vertex_count() * TRIPLANAR_STRIDE;
+        let plain = mesh.texture_coordinates.len() == mesh.vertex_count() * 2;
+        for t in mesh.indices.chunks_exact(3) {
+            let p = [mesh.position(t[0]), mesh.position(t[1]), mesh.position(t[2])];
+            let face = glm::cross(&(p[1] - p[0]), &(p[2] - p[0]));
+            let vertex = |k: usize| {
+                let i = t[k] as usize * 3;
+                let n = if smooth {
+                    glm::vec3(mesh.normals[i], mesh.normals[i + 1], mesh.normals[i + 2])
+                } else {
+                    face
+                };
+                let v = t[k] as usize;
+                let tex = if triplanar {
+                    &mesh.texture_coordinates[v * TRIPLANAR_STRIDE..][..TRIPLANAR_STRIDE]
+                } else {
+                    &[0.0; TRIPLANAR_STRIDE]
+                };
+                ClipVertex {
+                    position: mvp * glm::vec4(p[k].x, p[k].y, p[k].z, 1.0),
+                    normal: normal_matrix * n,
+                    color: if colored { glm::make_vec4(&mesh.colors[v * COLOR_STRIDE..][..COLOR_STRIDE]) } else { color },
+                    occlusion: if occluded { mesh.occlusion[v] } else { 1.0 },
+                    tex_weights: glm::make_vec3(&tex[..3]),
+                    tex_uv: [0, 1, 2].map(|a| glm::vec2(tex[3 + a * 2], tex[4 + a * 2])),
+                    tex_coord: if plain {
+                        glm::vec2(mesh.texture_coordinates[v * 2], mesh.texture_coordinates[v * 2 + 1])
+                    } else {
+                        glm::Vec2::zeros()
+                    },
+                }
+            };
+            let polygon = clip_near([vertex(0), vertex(1), vertex(2)]);
+            for i in 1..polygon.len().saturating_sub(1) {
+                self.fill([polygon[0], polygon[i], polygon[i + 1]], &light_dir);
+            }
+        }
+    }
+
+    /// Scan convert one clipped triangle, sampling at pixel centres with a
+    /// top-left fill rule and perspective correct attributes
+    fn fill(&mut self, t: [ClipVertex; 3], light_dir: &glm::Vec3) {
+        let (w, h) = (self.width as f32, self.height as f32);
+        // Window coordinates with y down, depth in [0, 1], and 1/w
+        let s = t.map(|v| {
+            let inv_w = 1.0 / v.position.w;
+            let ndc = v.position.xyz() * inv_w;
+            glm::vec4((ndc.x + 1.0) * 0.5 * w, (1.0 - ndc.y) * 0.5 * h, (ndc.z + 1.0) * 0.5, inv_w)
+        });
+        let edge = |a: &glm::Vec4, b: &glm::Vec4, x: f32, y: f32| (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x);
+        let area = edge(&s[0], &s[1], s[2].x, s[2].y);
+        if area == 0.0 || !area.is_finite() { return }
+        // Counter-clockwise in GL window space is clockwise with y down
+        let front = area < 0.0;
+        let sign = area.signum();
+
+        let min_x = s.iter().map(|v| v.x).fold(f32::INFINITY, f32::min).floor().max(0.0) as usize;
+        let max_x = s.iter().map(|v| v.x).fold(f32::NEG_INFINITY, f32::max).ceil().min(w) as usize;
+        let min_y = s.iter().map(|v| v.y).fold(f32::INFINITY, f32::min).floor().max(0.0) as usize;
+        let max_y = s.iter().map(|v| v.y).fold(f32::NEG_INFINITY, f32::max).ceil().min(h) as usize;
+        // Pixel centres exactly on an edge belong to top and left edges only
+        let owns = |a: &glm::Vec4, b: &glm::Vec4| {
+            let (dx, dy) = ((b.x - a.x) * sign, (b.y - a.y) * sign);
+            dy < 0.0 || (dy == 0.0 && dx > 0.0)
+        };
+        let edges = [(1, 2), (2, 0), (0, 1)];
+        let top_left = edges.map(|(a, b)| owns(&s[a], &s[b]));
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let mut b = [0.0; 3];
+                let mut covered = true;
+                for (k, &(i, j)) in edges.iter().enumerate() {
+                    let e = edge(&s[i], &s[j], px, py) * sign;
+                    covered &= e > 0.0 || (e == 0.0 && top_left[k]);
+                    b[k] = e / (area * sign);
+                }
+                if !covered { continue }
+                let z = b[0] * s[0].z + b[1] * s[1].z + b[2] * s[2].z;
+                let i = y * self.width + x;
+                if !(0.0..=1.0).contains(&z) || z >= self.depth[i] { continue }
+                self.depth[i] = z;
+
+                let pw = [b[0] * s[0].w, b[1] * s[1].w, b[2] * s[2].w];
+                let sum = pw[0] + pw[1] + pw[2];
+                let v = ClipVertex::mix([0, 1, 2].map(|k| (&t[k], pw[k] / sum)));
+                let mut diffuse = if glm::length2(&v.normal) > 0.0 {
+                    glm::dot(&glm::normalize(&v.normal), light_dir).max(0.0)
+                } else {
+                    0.0
+                };
+                let tex = glm::dot(&v.tex_weights, &glm::Vec3::from(v.tex_uv.map(|uv| checker(&uv))))
+                    + checker(&v.tex_coord);
+                let mut col = v.color.xyz() * (1.0 - CHECKER_DARKEN * tex);
+                if !front {
+                    diffuse = 1.0 - diffuse;
+                    col = glm::make_vec3(&BACK_FACE_COLOR);
+                }
+                let c = col * AMBIENT * v.occlusion + col * diffuse;
+                self.color[i] = glm::vec4(c.x, c.y, c.z, 1.0);
+            }
+        }
+    }
+
+    pub fn to_image(&self) -> image::RgbaImage {
+        let bytes = self.color.iter()
+            .flat_map(|c| c.iter().map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8).collect::<Vec<_>>())
+            .collect();
+        image::RgbaImage::from_raw(self.width as u32, self.height as u32, bytes)
+            .expect("framebuffer size matches image size")
+    }
+
+    /// Write the colour buffer, in the format given by the file extension
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> image::ImageResult<()> {
+        self.to_image().save(path)
+    }
+}
+
+/// Render a preview of the whole mesh from a fixed viewing direction, on
+/// the clear colour used by the viewer
+#[allow(unused)]
+pub fn render_preview<P: AsRef<Path>>(mesh: &Mesh, width: usize, height: usize, color: glm::Vec4, path: P) -> image::ImageResult<()> {
+    let mut fb = Framebuffer::new(width, height, glm::vec4(0.163, 0.163, 0.163, 1.0));
+    if let Some((min, max)) = mesh.bounds() {
+        let camera = Camera::framing(min, max, glm::vec3(-1.0, -0.8, -1.5), 1.0, fb.aspect());
+        fb.draw_mesh(mesh, &glm::identity(), &camera, color);
+    }
+    fb.save(path)
+}
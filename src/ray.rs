@@ -0,0 +1,248 @@
+// Ray casting against scalar fields and meshes
+
+use crate::field::{Grid, ScalarField};
+use crate::mc::Mesh;
+
+/// Bisection steps refining a bracketed field crossing
+const BISECTION_STEPS: usize = 32;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: glm::Vec3,
+    /// Unit direction
+    pub dir: glm::Vec3,
+}
+
+#[allow(unused)]
+impl Ray {
+    pub fn new(origin: glm::Vec3, dir: glm::Vec3) -> Self {
+        Ray { origin, dir: glm::normalize(&dir) }
+    }
+
+    pub fn at(&self, t: f32) -> glm::Vec3 {
+        self.origin + self.dir * t
+    }
+
+    /// Ray through window position (x, y), in pixels from the top left, for
+    /// a camera with the given combined projection and view matrix
+    pub fn from_screen(x: f32, y: f32, width: f32, height: f32, view_proj: &glm::Mat4) -> Self {
+        let inv = glm::inverse(view_proj);
+        let ndc = glm::vec2(2.0 * x / width - 1.0, 1.0 - 2.0 * y / height);
+        let unproject = |z: f32| {
+            let p = inv * glm::vec4(ndc.x, ndc.y, z, 1.0);
+            p.xyz() / p.w
+        };
+        let near = unproject(-1.0);
+        Ray::new(near, unproject(1.0) - near)
+    }
+}
+
+/// What a ray hit
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HitId {
+    /// Triangle index into `Mesh::indices`, in units of triangles
+    Triangle(u32),
+    /// Lattice cell of a `Grid`
+    Voxel([usize; 3]),
+    /// A field without a lattice
+    Field,
+}
+
+#[allow(unused)]
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub point: glm::Vec3,
+    /// Unit normal. For fields the normalised gradient, for triangles the
+    /// face normal following the winding; either way pointing out of the
+    /// surface, so it may face away from the ray
+    pub normal: glm::Vec3,
+    pub distance: f32,
+    pub id: HitId,
+}
+
+/// Möller-Trumbore intersection, hitting both sides. Returns the distance
+/// along the ray if within `max_distance`
+pub fn ray_triangle(ray: &Ray, v: &[glm::Vec3; 3], max_distance: f32) -> Option<f32> {
+    let (e1, e2) = (v[1] - v[0], v[2] - v[0]);
+    let p = glm::cross(&ray.dir, &e2);
+    let det = glm::dot(&e1, &p);
+    if det.abs() < f32::EPSILON * glm::length2(&e1).max(glm::length2(&e2)) { return None }
+    let inv = 1.0 / det;
+    let s = ray.origin - v[0];
+    let u = glm::dot(&s, &p) * inv;
+    if !(0.0..=1.0).contains(&u) { return None }
+    let q = glm::cross(&s, &e1);
+    let w = glm::dot(&ray.dir, &q) * inv;
+    if w < 0.0 || u + w > 1.0 { return None }
+    let t = glm::dot(&e2, &q) * inv;
+    (t >= 0.0 && t <= max_distance).then_some(t)
+}
+
+/// Slab test against an axis-aligned box. Returns the distances along the
+/// ray where it enters and leaves the box, clipped to [0, max_distance]
+pub fn ray_aabb(ray: &Ray, min: &glm::Vec3, max: &glm::Vec3, max_distance: f32) -> Option<(f32, f32)> {
+    let (mut t0, mut t1) = (0.0f32, max_distance);
+    for c in 0..3 {
+        let inv = 1.0 / ray.dir[c];
+        let (a, b) = ((min[c] - ray.origin[c]) * inv, (max[c] - ray.origin[c]) * inv);
+        // NaN from 0 * inf, for rays in the slab's plane, is ignored by max/min
+        t0 = t0.max(a.min(b));
+        t1 = t1.min(a.max(b));
+    }
+    (t0 <= t1).then_some((t0, t1))
+}
+
+/// Unit face normal following the winding, or zero for degenerate triangles
+pub fn face_normal(v: &[glm::Vec3; 3]) -> glm::Vec3 {
+    let n = glm::cross(&(v[1] - v[0]), &(v[2] - v[0]));
+    if glm::length2(&n) > 0.0 { glm::normalize(&n) } else { n }
+}
+
+/// Refine a crossing of `isolevel` known to lie between t0 and t1
+fn bisect<F: ScalarField + ?Sized>(field: &F, ray: &Ray, isolevel: f64, mut t0: f32, mut t1: f32) -> f32 {
+    let inside0 = field.value(ray.at(t0)) < isolevel;
+    for _ in 0..BISECTION_STEPS {
+        let t = 0.5 * (t0 + t1);
+        if (field.value(ray.at(t)) < isolevel) == inside0 { t0 = t } else { t1 = t }
+    }
+    0.5 * (t0 + t1)
+}
+
+fn field_hit<F: ScalarField + ?Sized>(field: &F, ray: &Ray, t: f32, h: f32, id: HitId) -> RayHit {
+    let point = ray.at(t);
+    let g = field.gradient(point, h);
+    let n = glm::vec3(g.x as f32, g.y as f32, g.z as f32);
+    RayHit {
+        point,
+        normal: if glm::length2(&n) > 0.0 { glm::normalize(&n) } else { -ray.dir },
+        distance: t,
+        id,
+    }
+}
+
+/// First crossing of `isolevel` along the ray, found by marching in fixed
+/// steps until the inside/outside state changes and bisecting that
+/// interval. Works for any field, but crossings thinner than `step` can be
+/// missed
+#[allow(unused)]
+pub fn bracket_trace<F: ScalarField + ?Sized>(
+    field: &F,
+    ray: &Ray,
+    isolevel: f64,
+    max_distance: f32,
+    step: f32,
+) -> Option<RayHit> {
+    let inside = field.value(ray.origin) < isolevel;
+    let mut t = 0.0;
+    while t < max_distance {
+        let next = (t + step).min(max_distance);
+        if (field.value(ray.at(next)) < isolevel) != inside {
+            let t = bisect(field, ray, isolevel, t, next);
+            return Some(field_hit(field, ray, t, step * 0.5, HitId::Field))
+        }
+        t = next;
+    }
+    None
+}
+
+/// First crossing of `isolevel` along the ray by sphere tracing. The field
+/// must not change faster than `lipschitz` per unit distance, e.g. 1 for a
+/// true signed distance field, so that |value - isolevel| / lipschitz is a
+/// safe step. Stops within `epsilon` of the surface
+#[allow(unused)]
+pub fn sphere_trace<F: ScalarField + ?Sized>(
+    field: &F,
+    ray: &Ray,
+    isolevel: f64,
+    max_distance: f32,
+    lipschitz: f64,
+    epsilon: f32,
+) -> Option<RayHit> {
+    let inside = field.value(ray.origin) < isolevel;
+    let (mut prev_t, mut t) = (0.0, 0.0);
+    while t < max_distance {
+        let v = field.value(ray.at(t));
+        if (v < isolevel) != inside {
+            // The last step crossed, e.g. from an underestimated Lipschitz
+            // bound, so the crossing lies within it
+            let t = bisect(field, ray, isolevel, prev_t, t);
+            return Some(field_hit(field, ray, t, epsilon, HitId::Field))
+        }
+        let d = ((v - isolevel).abs() / lipschitz) as f32;
+        if d < epsilon {
+            return Some(field_hit(field, ray, t, epsilon, HitId::Field))
+        }
+        prev_t = t;
+        t += d;
+    }
+    None
+}
+
+#[allow(unused)]
+impl Grid<'_> {
+    /// First crossing of `isolevel` inside the sampled volume, marching at
+    /// half the lattice spacing. The hit reports the lattice cell it lies in
+    pub fn raycast(&self, ray: &Ray, isolevel: f64, max_distance: f32) -> Option<RayHit> {
+        let (nx, ny, nz) = self.dims();
+        if nx < 2 || ny < 2 || nz < 2 { return None }
+        let max = glm::vec3((nx - 1) as f32, (ny - 1) as f32, (nz - 1) as f32) * self.scale;
+        let (t0, t1) = ray_aabb(ray, &glm::zero(), &max, max_distance)?;
+        let inner = Ray { origin: ray.at(t0), dir: ray.dir };
+        let mut hit = bracket_trace(self, &inner, isolevel, t1 - t0, self.scale * 0.5)?;
+        hit.distance += t0;
+        hit.id = self.cell(hit.point).map_or(HitId::Field, HitId::Voxel);
+        Some(hit)
+    }
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Nearest triangle hit by brute force over all triangles
+    pub fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<RayHit> {
+        let mut best: Option<RayHit> = None;
+        for (f, t) in self.indices.chunks_exact(3).enumerate() {
+            let v = [self.position(t[0]), self.position(t[1]), self.position(t[2])];
+            let max = best.map_or(max_distance, |b| b.distance);
+            if let Some(d) = ray_triangle(ray, &v, max) {
+                best = Some(RayHit {
+                    point: ray.at(d),
+                    normal: face_normal(&v),
+                    distance: d,
+                    id: HitId::Triangle(f as u32),
+                });
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sphere_trace_bisects_overshooting_steps() {
+        let epsilon = 1e-3;
+        let ray = Ray { origin: glm::vec3(0.0, 0.0, -20.0), dir: glm::Vec3::z() };
+        // Sphere of radius 10, and a plane, changing 1.5 and 3 times faster
+        // than the Lipschitz bound of 1 given, so the first step lands inside
+        let sphere = |p: glm::Vec3| 1.5 * (glm::length(&p) as f64 - 10.0);
+        let plane = |p: glm::Vec3| 3.0 * p.z as f64;
+        let hit = sphere_trace(&sphere, &ray, 0.0, 100.0, 1.0, epsilon).unwrap();
+        assert!((glm::length(&hit.point) - 10.0).abs() < epsilon, "{:?}", hit.point);
+        assert!((hit.distance - 10.0).abs() < epsilon);
+        let hit = sphere_trace(&plane, &ray, 0.0, 100.0, 1.0, epsilon).unwrap();
+        assert!(hit.point.z.abs() < epsilon, "{:?}", hit.point);
+    }
+
+    #[test]
+    fn sphere_trace_with_true_bound_converges() {
+        let epsilon = 1e-3;
+        let ray = Ray { origin: glm::vec3(1.0, 2.0, -20.0), dir: glm::Vec3::z() };
+        let sphere = |p: glm::Vec3| glm::length(&p) as f64 - 10.0;
+        let hit = sphere_trace(&sphere, &ray, 0.0, 100.0, 1.0, epsilon).unwrap();
+        assert!((glm::length(&hit.point) - 10.0).abs() < epsilon);
+        assert!(sphere_trace(&sphere, &ray, 0.0, 5.0, 1.0, epsilon).is_none());
+    }
+}
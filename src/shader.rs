@@ -1,4 +1,3 @@
-use gl;
 use std::{
     ptr,
     str,
@@ -35,9 +34,9 @@ impl Shader {
     }
 }
 
-impl Into<gl::types::GLenum> for ShaderType {
-    fn into(self) -> gl::types::GLenum {
-        match self {
+impl From<ShaderType> for gl::types::GLenum {
+    fn from(shader_type: ShaderType) -> gl::types::GLenum {
+        match shader_type {
             ShaderType::Vertex                  => { gl::VERTEX_SHADER          },
             ShaderType::Fragment                => { gl::FRAGMENT_SHADER        },
             ShaderType::TessellationControl     => { gl::TESS_CONTROL_SHADER    },
@@ -74,7 +73,7 @@ impl ShaderBuilder {
             let shader_type = ShaderType::from_ext(extension)
                 .expect("Failed to parse file extension.");
             let shader_src = std::fs::read_to_string(path)
-                .expect(&format!("Failed to read shader source. {}", shader_path));
+                .unwrap_or_else(|_| panic!("Failed to read shader source. {}", shader_path));
             self.compile_shader(&shader_src, shader_type)
         } else {
             panic!("Failed to read extension of file with path: {}", shader_path);
@@ -98,8 +97,7 @@ impl ShaderBuilder {
 
     unsafe fn check_shader_errors(&self, shader_id: u32) -> bool {
         let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
+        let mut info_log = vec![0u8; 512];
         gl::GetShaderiv(shader_id, gl::COMPILE_STATUS, &mut success);
         if success != i32::from(gl::TRUE) {
             gl::GetShaderInfoLog(
@@ -116,8 +114,7 @@ impl ShaderBuilder {
 
     unsafe fn check_linker_errors(&self) -> bool {
         let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
+        let mut info_log = vec![0u8; 512];
         gl::GetProgramiv(self.program_id, gl::LINK_STATUS, &mut success);
         if success != i32::from(gl::TRUE) {
             gl::GetProgramInfoLog(
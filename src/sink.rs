@@ -0,0 +1,268 @@
+// Triangle sinks: destinations for the triangles produced by marching cubes
+
+use std::io::{self, Seek, SeekFrom, Write};
+use crate::mc::Mesh;
+
+/// A single triangle as emitted by marching cubes. The normal is the
+/// unnormalised face normal, as stored in `Mesh::normals`
+#[derive(Clone, Copy, Debug)]
+pub struct Triangle {
+    pub vertices: [glm::Vec3; 3],
+    pub normal: glm::Vec3,
+}
+
+/// Anything that can consume a stream of triangles
+pub trait TriangleSink {
+    fn triangle(&mut self, t: &Triangle);
+}
+
+impl<S: TriangleSink + ?Sized> TriangleSink for &mut S {
+    fn triangle(&mut self, t: &Triangle) {
+        (**self).triangle(t)
+    }
+}
+
+/// Feed the same triangles to two sinks, e.g. `(&mut bounds, &mut count)`
+impl<A: TriangleSink, B: TriangleSink> TriangleSink for (A, B) {
+    fn triangle(&mut self, t: &Triangle) {
+        self.0.triangle(t);
+        self.1.triangle(t);
+    }
+}
+
+/// Building a `Mesh` with vertices duplicated and flat normals
+impl TriangleSink for Mesh {
+    fn triangle(&mut self, t: &Triangle) {
+        let i0 = self.vertices.len() as u32 / 3;
+        let [t0, t1, t2] = t.vertices;
+        let n = t.normal;
+        self.indices.extend_from_slice(&[i0, i0+1, i0+2]);
+        self.vertices.extend_from_slice(&[
+            t0.x, t0.y, t0.z,
+            t1.x, t1.y, t1.z,
+            t2.x, t2.y, t2.z,
+        ]);
+        self.normals.extend_from_slice(&[
+            n.x, n.y, n.z,
+            n.x, n.y, n.z,
+            n.x, n.y, n.z,
+        ]);
+        self.index_count += 3;
+    }
+}
+
+#[allow(unused)]
+/// Count triangles without storing them
+#[derive(Default, Debug)]
+pub struct CountingSink {
+    pub triangles: usize,
+}
+impl TriangleSink for CountingSink {
+    fn triangle(&mut self, _t: &Triangle) {
+        self.triangles += 1;
+    }
+}
+
+#[allow(unused)]
+/// Axis-aligned bounding box of all triangles seen so far
+#[derive(Default, Debug)]
+pub struct BoundsSink {
+    pub bounds: Option<(glm::Vec3, glm::Vec3)>,
+}
+impl TriangleSink for BoundsSink {
+    fn triangle(&mut self, t: &Triangle) {
+        for v in t.vertices.iter() {
+            self.bounds = Some(match self.bounds {
+                Some((min, max)) => (glm::min2(&min, v), glm::max2(&max, v)),
+                None => (*v, *v),
+            });
+        }
+    }
+}
+
+/// Write triangles straight to a binary STL file. The triangle count in the
+/// header is patched in by `finish`, which must be called. Streaming more
+/// than `u32::MAX` triangles makes `finish` fail
+pub struct StlWriter<W: Write + Seek> {
+    out: W,
+    start: u64,
+    count: u32,
+    error: Option<io::Error>,
+}
+#[allow(unused)]
+impl<W: Write + Seek> StlWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        let start = out.stream_position()?;
+        let mut header = [0u8; 80];
+        header[..14].copy_from_slice(b"marching cubes");
+        out.write_all(&header)?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(StlWriter { out, start, count: 0, error: None })
+    }
+
+    /// Patch the triangle count and return the underlying writer, or the
+    /// first error encountered while streaming
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() { return Err(e) }
+        let end = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(self.start + 80))?;
+        self.out.write_all(&self.count.to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_triangle(&mut self, t: &Triangle) -> io::Result<()> {
+        let n = if glm::length2(&t.normal) > 0.0 { glm::normalize(&t.normal) } else { t.normal };
+        let mut buf = [0u8; 50];
+        for (i, v) in [n, t.vertices[0], t.vertices[1], t.vertices[2]].iter().enumerate() {
+            for c in 0..3 {
+                let o = i*12 + c*4;
+                buf[o..o+4].copy_from_slice(&v[c].to_le_bytes());
+            }
+        }
+        self.out.write_all(&buf)
+    }
+}
+impl<W: Write + Seek> TriangleSink for StlWriter<W> {
+    fn triangle(&mut self, t: &Triangle) {
+        if self.error.is_some() { return }
+        if self.count == u32::MAX {
+            self.error = Some(too_many_triangles("STL", u32::MAX as u64));
+            return;
+        }
+        match self.write_triangle(t) {
+            Ok(()) => self.count += 1,
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+fn too_many_triangles(format: &str, limit: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{} files hold at most {} triangles", format, limit))
+}
+
+/// Width reserved for the element counts in the PLY header, so they can be
+/// patched in place once the totals are known. Fits `u32::MAX`
+const PLY_COUNT_WIDTH: usize = 10;
+/// Most triangles whose vertices can all be numbered by the u32 indices of
+/// the face list
+const PLY_MAX_TRIANGLES: u32 = u32::MAX / 3;
+
+/// Write triangles straight to a binary little-endian PLY file, with vertex
+/// positions and normals. Vertices are not shared between faces, so the face
+/// list is implied by the triangle count and written out by `finish`, which
+/// must be called. Streaming more than `PLY_MAX_TRIANGLES` triangles makes
+/// `finish` fail
+pub struct PlyWriter<W: Write + Seek> {
+    out: W,
+    start: u64,
+    count: u32,
+    error: Option<io::Error>,
+}
+#[allow(unused)]
+impl<W: Write + Seek> PlyWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        let start = out.stream_position()?;
+        out.write_all(Self::header(0).as_bytes())?;
+        Ok(PlyWriter { out, start, count: 0, error: None })
+    }
+
+    fn header(triangles: u32) -> String {
+        format!(
+            "ply\nformat binary_little_endian 1.0\ncomment marching cubes\n\
+            element vertex {:<w$}\n\
+            property float x\nproperty float y\nproperty float z\n\
+            property float nx\nproperty float ny\nproperty float nz\n\
+            element face {:<w$}\n\
+            property list uchar uint vertex_indices\nend_header\n",
+            triangles as u64 * 3, triangles, w = PLY_COUNT_WIDTH
+        )
+    }
+
+    /// Append the faces, patch the element counts and return the underlying
+    /// writer, or the first error encountered while streaming
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() { return Err(e) }
+        // Vertices are written in triangle order, so faces are just 0,1,2,3,...
+        // The count is at most PLY_MAX_TRIANGLES, so every index fits in u32
+        let mut face = [0u8; 13];
+        face[0] = 3;
+        for i in 0..self.count {
+            for c in 0..3 {
+                face[1+c*4..5+c*4].copy_from_slice(&(i*3 + c as u32).to_le_bytes());
+            }
+            self.out.write_all(&face)?;
+        }
+        let end = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(self.start))?;
+        self.out.write_all(Self::header(self.count).as_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_triangle(&mut self, t: &Triangle) -> io::Result<()> {
+        let n = if glm::length2(&t.normal) > 0.0 { glm::normalize(&t.normal) } else { t.normal };
+        let mut buf = [0u8; 72];
+        for (i, v) in t.vertices.iter().enumerate() {
+            for c in 0..3 {
+                let o = i*24 + c*4;
+                buf[o..o+4].copy_from_slice(&v[c].to_le_bytes());
+                buf[o+12..o+16].copy_from_slice(&n[c].to_le_bytes());
+            }
+        }
+        self.out.write_all(&buf)
+    }
+}
+impl<W: Write + Seek> TriangleSink for PlyWriter<W> {
+    fn triangle(&mut self, t: &Triangle) {
+        if self.error.is_some() { return }
+        if self.count == PLY_MAX_TRIANGLES {
+            self.error = Some(too_many_triangles("PLY", PLY_MAX_TRIANGLES as u64));
+            return;
+        }
+        match self.write_triangle(t) {
+            Ok(()) => self.count += 1,
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn triangle() -> Triangle {
+        Triangle { vertices: [glm::Vec3::zeros(), glm::Vec3::x(), glm::Vec3::y()], normal: glm::Vec3::z() }
+    }
+
+    #[test]
+    fn ply_counts_patched() {
+        let mut ply = PlyWriter::new(Cursor::new(Vec::new())).unwrap();
+        (0..5).for_each(|_| ply.triangle(&triangle()));
+        let bytes = ply.finish().unwrap().into_inner();
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(text.contains("element vertex 15 "));
+        assert!(text.contains("element face 5 "));
+        assert_eq!(bytes.len(), PlyWriter::<Cursor<Vec<u8>>>::header(0).len() + 5 * 72 + 5 * 13);
+    }
+
+    #[test]
+    fn ply_rejects_too_many_triangles() {
+        let mut ply = PlyWriter::new(Cursor::new(Vec::new())).unwrap();
+        ply.count = PLY_MAX_TRIANGLES;
+        assert_eq!(PlyWriter::<Cursor<Vec<u8>>>::header(PLY_MAX_TRIANGLES).len(), PlyWriter::<Cursor<Vec<u8>>>::header(0).len());
+        ply.triangle(&triangle());
+        assert_eq!(ply.finish().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn stl_rejects_too_many_triangles() {
+        let mut stl = StlWriter::new(Cursor::new(Vec::new())).unwrap();
+        stl.count = u32::MAX;
+        stl.triangle(&triangle());
+        assert_eq!(stl.finish().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,247 @@
+// Cross-sections of fields and meshes by planes
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::colormap::Colormap;
+use crate::field::{sample_plane, Grid, ScalarField};
+use crate::mc::{Mesh, SnapPolicy};
+use crate::squares::{join_segments, marching_squares, Contour};
+
+/// A plane with an orthonormal frame. Points on it have 2D coordinates
+/// along `u` and `v`, and its normal is `u × v`
+#[derive(Clone, Copy, Debug)]
+pub struct Plane {
+    pub origin: glm::Vec3,
+    pub u: glm::Vec3,
+    pub v: glm::Vec3,
+}
+
+#[allow(unused)]
+impl Plane {
+    /// Plane through `origin` with the given normal
+    pub fn new(origin: glm::Vec3, normal: glm::Vec3) -> Self {
+        let n = glm::normalize(&normal);
+        // Start from the world axis least aligned with the normal
+        let a = if n.x.abs() < 0.9 { glm::Vec3::x() } else { glm::Vec3::y() };
+        let u = glm::normalize(&(a - n * glm::dot(&a, &n)));
+        Plane { origin, u, v: glm::cross(&n, &u) }
+    }
+
+    /// Plane at `offset` along world axis 0, 1 or 2, with `u` and `v` the
+    /// following axes in cyclic order, e.g. y and z for the x axis
+    pub fn axis(axis: usize, offset: f32) -> Self {
+        let e = |c: usize| {
+            let mut e = glm::Vec3::zeros();
+            e[c % 3] = 1.0;
+            e
+        };
+        Plane { origin: e(axis) * offset, u: e(axis + 1), v: e(axis + 2) }
+    }
+
+    pub fn normal(&self) -> glm::Vec3 {
+        glm::cross(&self.u, &self.v)
+    }
+
+    pub fn distance(&self, p: &glm::Vec3) -> f32 {
+        glm::dot(&(p - self.origin), &self.normal())
+    }
+
+    /// Plane coordinates of the orthogonal projection of `p`
+    pub fn project(&self, p: &glm::Vec3) -> glm::Vec2 {
+        let d = p - self.origin;
+        glm::vec2(glm::dot(&d, &self.u), glm::dot(&d, &self.v))
+    }
+
+    /// World position of a point in plane coordinates
+    pub fn unproject(&self, p: &glm::Vec2) -> glm::Vec3 {
+        self.origin + self.u * p.x + self.v * p.y
+    }
+}
+
+/// Field values sampled on a rectangle of a plane, point (i, j) at plane
+/// coordinates `min + (i, j) * spacing`
+#[allow(unused)]
+pub struct Slice {
+    pub plane: Plane,
+    pub min: glm::Vec2,
+    pub spacing: f32,
+    pub values: Vec<Vec<f64>>,
+}
+
+#[allow(unused)]
+impl Slice {
+    /// Sample the rectangle from `min` of the given size, in plane
+    /// coordinates
+    pub fn sample<F: ScalarField + ?Sized>(field: &F, plane: &Plane, min: glm::Vec2, size: glm::Vec2, spacing: f32) -> Self {
+        let n = size.map(|s| (s / spacing).floor().max(0.0) as usize + 1);
+        let values = sample_plane(
+            field,
+            plane.unproject(&min),
+            plane.u * spacing,
+            plane.v * spacing,
+            n.x,
+            n.y,
+        );
+        Slice { plane: *plane, min, spacing, values }
+    }
+
+    /// Number of samples along `u` and `v`
+    pub fn dims(&self) -> (usize, usize) {
+        (self.values.len(), self.values.first().map_or(0, |v| v.len()))
+    }
+
+    /// Smallest and largest finite sampled value
+    pub fn range(&self) -> Option<(f64, f64)> {
+        self.values.iter().flatten().filter(|v| v.is_finite()).fold(None, |r, &v| match r {
+            None => Some((v, v)),
+            Some((lo, hi)) => Some((v.min(lo), v.max(hi))),
+        })
+    }
+
+    /// Isocontours in plane coordinates
+    pub fn contours(&self, isolevel: f64) -> Vec<Contour> {
+        let mut contours = marching_squares(&self.values, self.spacing, isolevel, SnapPolicy::None);
+        for c in contours.iter_mut() {
+            c.points.iter_mut().for_each(|p| *p += self.min);
+        }
+        contours
+    }
+
+    /// The samples as an image, one pixel per sample with `v` pointing up.
+    /// Values are mapped over `range`, or over the sampled range if `None`
+    pub fn to_image(&self, colormap: Colormap, range: Option<(f64, f64)>) -> image::RgbImage {
+        let (nu, nv) = self.dims();
+        let range = range.or_else(|| self.range()).unwrap_or((0.0, 1.0));
+        image::RgbImage::from_fn(nu as u32, nv as u32, |x, y| {
+            image::Rgb(colormap.rgb8(self.values[x as usize][nv - 1 - y as usize], range))
+        })
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P, colormap: Colormap, range: Option<(f64, f64)>) -> image::ImageResult<()> {
+        self.to_image(colormap, range).save_with_format(path, image::ImageFormat::Png)
+    }
+}
+
+#[allow(unused)]
+impl Grid<'_> {
+    /// Slice through the whole sampled volume, at lattice resolution unless
+    /// `spacing` is given
+    pub fn slice(&self, plane: &Plane, spacing: Option<f32>) -> Slice {
+        let (nx, ny, nz) = self.dims();
+        let max = glm::vec3(nx.saturating_sub(1) as f32, ny.saturating_sub(1) as f32, nz.saturating_sub(1) as f32) * self.scale;
+        // Bounds of the volume's corners projected onto the plane
+        let (mut lo, mut hi) = (glm::Vec2::repeat(f32::INFINITY), glm::Vec2::repeat(f32::NEG_INFINITY));
+        for c in 0..8 {
+            let p = glm::vec3(
+                if c & 4 != 0 { max.x } else { 0.0 },
+                if c & 2 != 0 { max.y } else { 0.0 },
+                if c & 1 != 0 { max.z } else { 0.0 },
+            );
+            let q = plane.project(&p);
+            lo = glm::min2(&lo, &q);
+            hi = glm::max2(&hi, &q);
+        }
+        Slice::sample(self, plane, lo, hi - lo, spacing.unwrap_or(self.scale))
+    }
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Polylines where the mesh crosses the plane, in plane coordinates.
+    /// Like isocontours they run with the inside of the mesh on the left,
+    /// and are closed where the mesh is. Vertices exactly on the plane are
+    /// treated as lying above it, so every crossing is a proper one
+    pub fn intersect_plane(&self, plane: &Plane) -> Vec<Contour> {
+        let bits = |p: &glm::Vec3| [(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()];
+        type Key = ([u32; 3], [u32; 3]);
+        let mut points: HashMap<Key, glm::Vec2> = HashMap::new();
+        let mut next: HashMap<Key, Key> = HashMap::new();
+        let normal = plane.normal();
+
+        for t in self.indices.chunks_exact(3) {
+            let p = [self.position(t[0]), self.position(t[1]), self.position(t[2])];
+            let d = p.map(|p| plane.distance(&p));
+            let mut crossing = Vec::with_capacity(2);
+            for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+                if (d[a] >= 0.0) == (d[b] >= 0.0) { continue }
+                // Interpolate from the lower end, so neighbours agree. A
+                // crossing exactly at a vertex is keyed by the vertex, as
+                // every edge from it crosses there
+                let (a, b) = if bits(&p[a]) < bits(&p[b]) { (a, b) } else { (b, a) };
+                let key = match (d[a] == 0.0, d[b] == 0.0) {
+                    (true, _) => (bits(&p[a]), bits(&p[a])),
+                    (_, true) => (bits(&p[b]), bits(&p[b])),
+                    _ => (bits(&p[a]), bits(&p[b])),
+                };
+                points.entry(key).or_insert_with(|| {
+                    let s = d[a] / (d[a] - d[b]);
+                    plane.project(&(p[a] + (p[b] - p[a]) * s))
+                });
+                crossing.push(key);
+            }
+            if crossing.len() != 2 || crossing[0] == crossing[1] { continue }
+            // With the outward face normal to the right, the inside is left.
+            // Degenerate triangles have no side and are skipped
+            let face = glm::cross(&(p[1] - p[0]), &(p[2] - p[0]));
+            if glm::length2(&face) == 0.0 { continue }
+            let forward = glm::cross(&normal, &face);
+            let dir = plane.unproject(&points[&crossing[1]]) - plane.unproject(&points[&crossing[0]]);
+            let (from, to) = if glm::dot(&dir, &forward) >= 0.0 {
+                (crossing[0], crossing[1])
+            } else {
+                (crossing[1], crossing[0])
+            };
+            next.insert(from, to);
+        }
+
+        join_segments(&points, next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::marching_cubes;
+
+    fn sphere(n: usize, radius: f64) -> Vec<Vec<Vec<f64>>> {
+        let c = n as f64 / 2.0;
+        (0..=n).map(|i| (0..=n).map(|j| (0..=n).map(|k| {
+            ((i as f64 - c).powi(2) + (j as f64 - c).powi(2) + (k as f64 - c).powi(2)).sqrt() - radius
+        }).collect()).collect()).collect()
+    }
+
+    #[test]
+    fn mesh_section_matches_field_contours() {
+        let (scale, radius) = (0.5, 5.5);
+        let points = sphere(16, radius);
+        let grid = Grid::new(&points, scale);
+        let mesh = marching_cubes((0, 0, 0), scale, &points, 0.0).weld();
+
+        // Through a lattice plane, where the mesh vertices are the
+        // marching squares points, and half way between two
+        for (k, exact) in [(7.0f32, true), (7.5, false)] {
+            let plane = Plane::axis(2, k * scale);
+            let section = mesh.intersect_plane(&plane);
+            let contours = grid.slice(&plane, None).contours(0.0);
+            assert_eq!(section.len(), 1, "{k}");
+            assert_eq!(contours.len(), 1, "{k}");
+            let (section, contour) = (&section[0], &contours[0]);
+            assert!(section.closed && contour.closed);
+            assert!(section.area() > 0.0 && contour.area() > 0.0);
+            assert!((section.area() - contour.area()).abs() < 0.02 * contour.area(), "{k}");
+
+            let r = (radius as f32 * radius as f32 - (k - 8.0).powi(2)).sqrt() * scale;
+            let centre = plane.project(&glm::Vec3::repeat(8.0 * scale));
+            for p in &section.points {
+                assert!((glm::distance(p, &centre) - r).abs() < 0.05, "{p:?}");
+            }
+            if exact {
+                assert_eq!(section.points.len(), contour.points.len());
+                for p in &section.points {
+                    assert!(contour.points.iter().any(|q| glm::distance(p, q) < 1e-5), "{p:?}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+// Laplacian and Taubin smoothing of welded meshes
+
+use crate::mc::Mesh;
+
+#[allow(unused)]
+#[derive(Clone, Copy, Debug)]
+pub enum SmoothMethod {
+    /// Move each vertex by `lambda` towards the average of its neighbours.
+    /// Shrinks the mesh over many iterations
+    Laplacian { lambda: f32 },
+    /// As `Laplacian`, with neighbours weighted by the cotangents of the
+    /// opposite angles, so that triangle shape does not cause tangential drift
+    Cotangent { lambda: f32 },
+    /// A uniform step of `lambda` followed by one of the negative `mu` each
+    /// iteration, which cancels the shrinkage. `mu` should satisfy
+    /// `mu < -lambda`
+    Taubin { lambda: f32, mu: f32 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SmoothOptions {
+    pub method: SmoothMethod,
+    pub iterations: usize,
+    /// Keep vertices on open boundary edges fixed, e.g. on chunk seams
+    pub lock_boundary: bool,
+}
+impl Default for SmoothOptions {
+    fn default() -> Self {
+        SmoothOptions {
+            method: SmoothMethod::Taubin { lambda: 0.5, mu: -0.53 },
+            iterations: 10,
+            lock_boundary: true,
+        }
+    }
+}
+
+/// Cotangent weight of every (vertex, neighbour) pair, in the order of
+/// `neighbours`. Negative weights from obtuse angles are clamped to zero
+fn cotangent_weights(mesh: &Mesh, pos: &[glm::Vec3], neighbours: &[Vec<u32>]) -> Vec<Vec<f32>> {
+    let mut weights = neighbours.iter().map(|n| vec![0.0; n.len()]).collect::<Vec<_>>();
+    for t in mesh.indices.chunks_exact(3) {
+        for i in 0..3 {
+            let (a, b, c) = (t[i], t[(i + 1) % 3], t[(i + 2) % 3]);
+            // Angle at c, opposite edge (a, b)
+            let (u, v) = (pos[a as usize] - pos[c as usize], pos[b as usize] - pos[c as usize]);
+            let sin = glm::length(&glm::cross(&u, &v));
+            if sin == 0.0 { continue }
+            let cot = 0.5 * glm::dot(&u, &v) / sin;
+            for (x, y) in [(a, b), (b, a)] {
+                let j = neighbours[x as usize].binary_search(&y).unwrap();
+                weights[x as usize][j] += cot;
+            }
+        }
+    }
+    for w in weights.iter_mut().flatten() {
+        *w = w.max(0.0);
+    }
+    weights
+}
+
+/// One Jacobi step moving every unlocked vertex by `lambda` times its
+/// (weighted) Laplacian
+fn laplacian_step(
+    pos: &mut [glm::Vec3],
+    neighbours: &[Vec<u32>],
+    weights: Option<&[Vec<f32>]>,
+    locked: &[bool],
+    lambda: f32,
+) {
+    let old = pos.to_vec();
+    for (i, p) in pos.iter_mut().enumerate() {
+        if locked[i] || neighbours[i].is_empty() { continue }
+        let mut sum = glm::Vec3::zeros();
+        let mut total = 0.0;
+        for (j, &n) in neighbours[i].iter().enumerate() {
+            let w = weights.map_or(1.0, |w| w[i][j]);
+            sum += (old[n as usize] - old[i]) * w;
+            total += w;
+        }
+        if total > 0.0 {
+            *p += sum * (lambda / total);
+        }
+    }
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Smooth vertex positions of a welded mesh in place, then recompute
+    /// the normals
+    pub fn smooth(&mut self, opts: &SmoothOptions) {
+        let neighbours = self.vertex_neighbours();
+        let locked = if opts.lock_boundary {
+            self.boundary_vertices()
+        } else {
+            vec![false; self.vertex_count()]
+        };
+        let mut pos = crate::util::to_array_of_vec3(self.vertices.clone());
+
+        for _ in 0..opts.iterations {
+            match opts.method {
+                SmoothMethod::Laplacian { lambda } => {
+                    laplacian_step(&mut pos, &neighbours, None, &locked, lambda);
+                },
+                SmoothMethod::Cotangent { lambda } => {
+                    let weights = cotangent_weights(self, &pos, &neighbours);
+                    laplacian_step(&mut pos, &neighbours, Some(&weights), &locked, lambda);
+                },
+                SmoothMethod::Taubin { lambda, mu } => {
+                    laplacian_step(&mut pos, &neighbours, None, &locked, lambda);
+                    laplacian_step(&mut pos, &neighbours, None, &locked, mu);
+                },
+            }
+        }
+
+        self.vertices = crate::util::from_array_of_vec3(pos);
+        self.recompute_normals();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    /// Move every vertex by up to `amount` along `direction(p)`
+    fn jitter(mesh: &mut Mesh, amount: f32, direction: impl Fn(glm::Vec3) -> glm::Vec3) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(29);
+        for p in mesh.vertices.chunks_exact_mut(3) {
+            let offset = direction(glm::vec3(p[0], p[1], p[2])) * rng.gen_range(-amount..amount);
+            for (x, d) in p.iter_mut().zip(offset.iter()) {
+                *x += d;
+            }
+        }
+    }
+
+    #[test]
+    fn lock_boundary_keeps_boundary_fixed() {
+        let mut noisy = Mesh::plane(glm::vec2(2.0, 2.0), (8, 8));
+        jitter(&mut noisy, 0.1, |_| glm::Vec3::y());
+        let boundary = noisy.boundary_vertices();
+        assert_eq!(boundary.iter().filter(|&&b| b).count(), 32);
+
+        for lock_boundary in [true, false] {
+            let mut mesh = Mesh { vertices: noisy.vertices.clone(), indices: noisy.indices.clone(), ..Default::default() };
+            mesh.smooth(&SmoothOptions { lock_boundary, ..Default::default() });
+            for (i, &on_boundary) in boundary.iter().enumerate() {
+                let moved = mesh.position(i as u32) != noisy.position(i as u32);
+                if on_boundary {
+                    assert_eq!(moved, !lock_boundary, "vertex {i}");
+                } else {
+                    assert!(moved, "vertex {i}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn taubin_keeps_volume_better_than_laplacian() {
+        let sphere = Mesh::icosphere(1.0, 3).weld();
+        let volume = sphere.volume();
+        let mut noisy = Mesh { vertices: sphere.vertices.clone(), indices: sphere.indices.clone(), ..Default::default() };
+        jitter(&mut noisy, 0.05, |p| glm::normalize(&p));
+        assert!(noisy.validate().is_watertight());
+
+        let smoothed_volume = |method| {
+            let mut mesh = Mesh { vertices: noisy.vertices.clone(), indices: noisy.indices.clone(), ..Default::default() };
+            mesh.smooth(&SmoothOptions { method, iterations: 10, lock_boundary: true });
+            mesh.volume()
+        };
+        let laplacian = smoothed_volume(SmoothMethod::Laplacian { lambda: 0.5 });
+        let taubin = smoothed_volume(SmoothMethod::Taubin { lambda: 0.5, mu: -0.53 });
+        assert!(laplacian < volume * 0.95, "{laplacian} {volume}");
+        assert!((taubin - volume).abs() < (laplacian - volume).abs() / 4.0, "{taubin} {laplacian} {volume}");
+    }
+}
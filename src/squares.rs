@@ -0,0 +1,218 @@
+// Marching squares, extracting isolines from 2D grids
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{self, Write};
+
+use crate::mc::{vertex_interp, SnapPolicy};
+
+/// Corners of a cell counter-clockwise from the lower left, as (di, dj)
+const CORNERS: [(usize, usize); 4] = [(0, 0), (1, 0), (1, 1), (0, 1)];
+
+/// Edges walked counter-clockwise around a cell, from corner to corner
+const EDGES: [(usize, usize); 4] = [(0, 1), (1, 2), (2, 3), (3, 0)];
+
+/// A lattice edge, as its lower lattice point and axis
+type EdgeKey = (usize, usize, u8);
+
+/// A connected isoline
+#[allow(unused)]
+#[derive(Clone, Debug)]
+pub struct Contour {
+    pub points: Vec<glm::Vec2>,
+    /// Closed contours do not repeat the first point at the end
+    pub closed: bool,
+}
+
+#[allow(unused)]
+impl Contour {
+    pub fn length(&self) -> f32 {
+        let open = self.points.windows(2).map(|w| glm::distance(&w[0], &w[1])).sum::<f32>();
+        match (self.closed, self.points.first(), self.points.last()) {
+            (true, Some(a), Some(b)) => open + glm::distance(a, b),
+            _ => open,
+        }
+    }
+
+    /// Signed area enclosed by a closed contour, positive when the inside
+    /// is enclosed
+    pub fn area(&self) -> f32 {
+        if !self.closed { return 0.0 }
+        let n = self.points.len();
+        (0..n).map(|i| {
+            let (a, b) = (self.points[i], self.points[(i + 1) % n]);
+            a.x * b.y - b.x * a.y
+        }).sum::<f32>() * 0.5
+    }
+}
+
+/// Lattice edge between corners `a` and `b` of cell (i, j)
+fn edge_key(i: usize, j: usize, a: usize, b: usize) -> EdgeKey {
+    let (pa, pb) = (CORNERS[a], CORNERS[b]);
+    let lower = (pa.0.min(pb.0), pa.1.min(pb.1));
+    let axis = if pa.0 != pb.0 { 0 } else { 1 };
+    (i + lower.0, j + lower.1, axis)
+}
+
+/// Isolines of `values`, with lattice point (i, j) at (i, j) * scale. As in
+/// marching cubes, values below the isolevel are inside. Contours run
+/// counter-clockwise around the inside, i.e. with the inside on the left,
+/// and are closed unless they leave the grid. Saddle cells, with inside
+/// corners diagonally opposite, are resolved with the asymptotic decider:
+/// the inside corners are joined if the bilinear interpolant is inside at
+/// its saddle point
+#[allow(unused)]
+pub fn marching_squares(values: &[Vec<f64>], scale: f32, isolevel: f64, snap: SnapPolicy) -> Vec<Contour> {
+    let nx = values.len();
+    let ny = values.first().map_or(0, |v| v.len());
+    let mut points: HashMap<EdgeKey, glm::Vec2> = HashMap::new();
+    let mut next: HashMap<EdgeKey, EdgeKey> = HashMap::new();
+
+    for i in 0..nx.saturating_sub(1) {
+        for j in 0..ny.saturating_sub(1) {
+            let val = CORNERS.map(|(di, dj)| values[i + di][j + dj]);
+            let inside = val.map(|v| v < isolevel);
+            // Crossings in counter-clockwise order, and whether the boundary
+            // leaves the inside there
+            let mut crossings = Vec::with_capacity(4);
+            for &(a, b) in EDGES.iter() {
+                if inside[a] == inside[b] { continue }
+                let key = edge_key(i, j, a, b);
+                points.entry(key).or_insert_with(|| {
+                    // Interpolate from the lower lattice point, so both cells
+                    // sharing the edge compute the same point
+                    let (lo, hi) = if CORNERS[a] < CORNERS[b] { (a, b) } else { (b, a) };
+                    let p = |c: usize| glm::vec2((i + CORNERS[c].0) as f32, (j + CORNERS[c].1) as f32) * scale;
+                    vertex_interp(isolevel, p(lo), p(hi), val[lo], val[hi], snap).0
+                });
+                crossings.push((key, inside[a]));
+            }
+            // Joining each exit to the next crossing keeps the inside of a
+            // saddle connected, joining it to the previous one separates it
+            let connect = crossings.len() == 4 && {
+                let d = val.map(|v| v - isolevel);
+                let denom = d[0] + d[2] - d[1] - d[3];
+                let saddle = if denom != 0.0 { (d[0] * d[2] - d[1] * d[3]) / denom } else { d.iter().sum::<f64>() / 4.0 };
+                saddle < 0.0
+            };
+            let n = crossings.len();
+            for k in 0..n {
+                let (key, exit) = crossings[k];
+                if !exit { continue }
+                let partner = if connect { (k + 1) % n } else { (k + n - 1) % n };
+                next.insert(key, crossings[partner].0);
+            }
+        }
+    }
+
+    join_segments(&points, next)
+}
+
+/// Join directed segments, given as a map from each start point to its end
+/// point, into contours. Chains without a predecessor become open contours,
+/// everything left forms closed loops
+pub fn join_segments<K: Copy + Ord + Hash>(points: &HashMap<K, glm::Vec2>, mut next: HashMap<K, K>) -> Vec<Contour> {
+    // Open chains first, from the points nothing leads to
+    let mut has_prev = next.values().copied().collect::<Vec<_>>();
+    has_prev.sort_unstable();
+    let mut open = next.keys().copied().filter(|k| has_prev.binary_search(k).is_err()).collect::<Vec<_>>();
+    open.sort_unstable();
+    let mut rest = next.keys().copied().collect::<Vec<_>>();
+    rest.sort_unstable();
+
+    let mut contours = Vec::new();
+    for start in open.into_iter().chain(rest) {
+        if !next.contains_key(&start) { continue }
+        let mut chain = vec![points[&start]];
+        let mut key = start;
+        let closed = loop {
+            match next.remove(&key) {
+                Some(k) if k == start => break true,
+                Some(k) => {
+                    chain.push(points[&k]);
+                    key = k;
+                },
+                None => break false,
+            }
+        };
+        contours.push(Contour { points: chain, closed });
+    }
+    contours
+}
+
+/// Write contours as an SVG document, flipping y so up is up. The view box
+/// is the bounds of the contours, padded by `stroke_width`
+#[allow(unused)]
+pub fn write_svg<W: Write>(out: &mut W, contours: &[Contour], stroke_width: f32) -> io::Result<()> {
+    let all = contours.iter().flat_map(|c| c.points.iter());
+    let (min, max) = all.fold(
+        (glm::Vec2::repeat(f32::INFINITY), glm::Vec2::repeat(f32::NEG_INFINITY)),
+        |(lo, hi), p| (glm::min2(&lo, p), glm::max2(&hi, p)),
+    );
+    let (min, max) = if min.x <= max.x { (min, max) } else { (glm::zero(), glm::zero()) };
+    let pad = stroke_width;
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">"#,
+        min.x - pad, -max.y - pad, max.x - min.x + 2.0 * pad, max.y - min.y + 2.0 * pad,
+    )?;
+    writeln!(out, r#"<g fill="none" stroke="black" stroke-width="{}" stroke-linejoin="round">"#, stroke_width)?;
+    for c in contours {
+        let points = c.points.iter().map(|p| format!("{},{}", p.x, -p.y)).collect::<Vec<_>>().join(" ");
+        let element = if c.closed { "polygon" } else { "polyline" };
+        writeln!(out, r#"<{} points="{}"/>"#, element, points)?;
+    }
+    writeln!(out, "</g>")?;
+    writeln!(out, "</svg>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circle_gives_one_ccw_contour() {
+        let (n, scale, radius) = (33, 0.25, 3.0);
+        let centre = (n - 1) as f32 * scale / 2.0;
+        let values = (0..n).map(|i| (0..n).map(|j| {
+            let p = glm::vec2(i as f32, j as f32) * scale - glm::Vec2::repeat(centre);
+            glm::length(&p) as f64 - radius as f64
+        }).collect()).collect::<Vec<Vec<f64>>>();
+
+        let contours = marching_squares(&values, scale, 0.0, SnapPolicy::None);
+        assert_eq!(contours.len(), 1);
+        let circle = &contours[0];
+        assert!(circle.closed);
+        for p in &circle.points {
+            assert!((glm::distance(p, &glm::Vec2::repeat(centre)) - radius).abs() < 0.02, "{p:?}");
+        }
+        let area = std::f32::consts::PI * radius * radius;
+        assert!(circle.area() > 0.0 && (circle.area() - area).abs() < 0.01 * area, "{}", circle.area());
+        assert!((circle.length() - std::f32::consts::TAU * radius).abs() < 0.01 * radius);
+    }
+
+    /// Outside ring around a saddle cell with inside corners (1, 1) and
+    /// (2, 2) at `inside`, and the other two at `outside`
+    fn saddle(inside: f64, outside: f64) -> Vec<Contour> {
+        let mut values = vec![vec![1.0; 4]; 4];
+        values[1][1] = inside;
+        values[2][2] = inside;
+        values[2][1] = outside;
+        values[1][2] = outside;
+        let contours = marching_squares(&values, 1.0, 0.0, SnapPolicy::None);
+        assert!(contours.iter().all(|c| c.closed && c.area() > 0.0), "{contours:?}");
+        contours
+    }
+
+    #[test]
+    fn saddles_follow_asymptotic_decider() {
+        // Inside at the saddle point: (1 - 0.25) / (-2 - 1) < 0
+        assert_eq!(saddle(-1.0, 0.5).len(), 1);
+        // Outside at the saddle point: (0.25 - 1) / (-1 - 2) > 0
+        assert_eq!(saddle(-0.5, 1.0).len(), 2);
+        // Symmetric values put the saddle point on the isolevel, and the
+        // inside is kept apart
+        assert_eq!(saddle(-1.0, 1.0).len(), 2);
+    }
+}
@@ -0,0 +1,43 @@
+// Projected texture coordinates
+
+use crate::mc::{Mesh, TexturePolicy};
+
+/// Floats per vertex written by `TexturePolicy::Triplanar`
+pub const TRIPLANAR_STRIDE: usize = 9;
+
+/// UV of `p` projected along `axis`, using the following axes in cyclic
+/// order so the projections are not mirrored against each other
+fn project(p: &glm::Vec3, axis: usize, scale: f32) -> [f32; 2] {
+    [p[(axis + 1) % 3] / scale, p[(axis + 2) % 3] / scale]
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Fill `texture_coordinates` following the policy, replacing any there.
+    /// Uses the vertex normals, or face normals if there are none
+    pub fn generate_texture_coordinates(&mut self, policy: TexturePolicy) {
+        let n = self.vertex_count();
+        let normals = self.vertex_normals();
+
+        self.texture_coordinates = match policy {
+            TexturePolicy::None => Vec::new(),
+            TexturePolicy::DominantAxis { scale } => (0..n).flat_map(|i| {
+                let a = normals[i].abs();
+                let axis = if a.x >= a.y && a.x >= a.z { 0 } else if a.y >= a.z { 1 } else { 2 };
+                project(&self.position(i as u32), axis, scale)
+            }).collect(),
+            TexturePolicy::Triplanar { scale, sharpness } => (0..n).flat_map(|i| {
+                let w = normals[i].abs().map(|x| x.powf(sharpness));
+                let sum = w.x + w.y + w.z;
+                let w = if sum > 0.0 { w / sum } else { glm::Vec3::repeat(1.0 / 3.0) };
+                let p = self.position(i as u32);
+                let mut out = [0.0; TRIPLANAR_STRIDE];
+                out[..3].copy_from_slice(w.as_slice());
+                for axis in 0..3 {
+                    out[3 + axis * 2..5 + axis * 2].copy_from_slice(&project(&p, axis, scale));
+                }
+                out
+            }).collect(),
+        };
+    }
+}
@@ -0,0 +1,49 @@
+// Adjacency queries on indexed meshes
+
+use std::collections::HashMap;
+use crate::mc::Mesh;
+
+#[allow(unused)]
+impl Mesh {
+    /// Triangles adjacent to each undirected edge, keyed by (low, high)
+    /// vertex index
+    pub fn edge_faces(&self) -> HashMap<(u32, u32), Vec<u32>> {
+        let mut edges: HashMap<(u32, u32), Vec<u32>> = HashMap::new();
+        for (f, t) in self.indices.chunks_exact(3).enumerate() {
+            for i in 0..3 {
+                let (a, b) = (t[i], t[(i + 1) % 3]);
+                edges.entry((a.min(b), a.max(b))).or_default().push(f as u32);
+            }
+        }
+        edges
+    }
+
+    /// Sorted list of vertices sharing an edge with each vertex
+    pub fn vertex_neighbours(&self) -> Vec<Vec<u32>> {
+        let mut neighbours = vec![Vec::new(); self.vertex_count()];
+        for t in self.indices.chunks_exact(3) {
+            for i in 0..3 {
+                let (a, b) = (t[i], t[(i + 1) % 3]);
+                neighbours[a as usize].push(b);
+                neighbours[b as usize].push(a);
+            }
+        }
+        for n in neighbours.iter_mut() {
+            n.sort_unstable();
+            n.dedup();
+        }
+        neighbours
+    }
+
+    /// Whether each vertex lies on an edge used by a single triangle
+    pub fn boundary_vertices(&self) -> Vec<bool> {
+        let mut boundary = vec![false; self.vertex_count()];
+        for (&(a, b), faces) in self.edge_faces().iter() {
+            if faces.len() == 1 {
+                boundary[a as usize] = true;
+                boundary[b as usize] = true;
+            }
+        }
+        boundary
+    }
+}
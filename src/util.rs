@@ -9,24 +9,21 @@ use itertools::Itertools;
 /// Convert an array of Vec2 into an array of numbers
 pub fn from_array_of_vec2<T: Scalar + Copy>(arr: Vec<glm::TVec2<T>>) -> Vec<T> {
     arr.iter()
-    .map(|v| vec![v[0], v[1]])
-    .flatten()
+    .flat_map(|v| [v[0], v[1]])
     .collect::<_>()
 }
 #[allow(unused)]
 /// Convert an array of Vec3 into an array of numbers
 pub fn from_array_of_vec3<T: Scalar + Copy>(arr: Vec<glm::TVec3<T>>) -> Vec<T> {
     arr.iter()
-    .map(|v| vec![v[0], v[1], v[2]])
-    .flatten()
+    .flat_map(|v| [v[0], v[1], v[2]])
     .collect::<_>()
 }
 #[allow(unused)]
 /// Convert an array of Vec4 into an array of numbers
 pub fn from_array_of_vec4<T: Scalar + Copy>(arr: Vec<glm::TVec4<T>>) -> Vec<T> {
     arr.iter()
-        .map(|v| vec![v[0], v[1], v[2], v[3]])
-        .flatten()
+        .flat_map(|v| [v[0], v[1], v[2], v[3]])
         .collect::<_>()
 }
 #[allow(unused)]
@@ -75,21 +72,27 @@ pub fn offset<T>(n: u32) -> *const c_void {
     (n * mem::size_of::<T>() as u32) as *const T as *const c_void
 }
 
+#[allow(unused)]
 pub fn vec4_f32_to_f64(v: &glm::TVec4<f32>) -> glm::TVec4<f64> {
     glm::vec4(v.x as _, v.y as _, v.z as _, v.w as _)
 }
+#[allow(unused)]
 pub fn vec4_f64_to_f632(v: &glm::TVec4<f64>) -> glm::TVec4<f32> {
     glm::vec4(v.x as _, v.y as _, v.z as _, v.w as _)
 }
+#[allow(unused)]
 pub fn vec3_f32_to_f64(v: &glm::TVec3<f32>) -> glm::TVec3<f64> {
     glm::vec3(v.x as _, v.y as _, v.z as _)
 }
+#[allow(unused)]
 pub fn vec3_f64_to_f632(v: &glm::TVec3<f64>) -> glm::TVec3<f32> {
     glm::vec3(v.x as _, v.y as _, v.z as _)
 }
+#[allow(unused)]
 pub fn vec2_f32_to_f64(v: &glm::TVec2<f32>) -> glm::TVec2<f64> {
     glm::vec2(v.x as _, v.y as _)
 }
+#[allow(unused)]
 pub fn vec2_f64_to_f632(v: &glm::TVec2<f64>) -> glm::TVec2<f32> {
     glm::vec2(v.x as _, v.y as _)
 }
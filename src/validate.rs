@@ -0,0 +1,201 @@
+// Mesh validation: manifoldness, watertightness and orientation
+
+use std::collections::HashMap;
+use crate::mc::Mesh;
+
+/// Problems found by `Mesh::validate`. Edges are (low, high) vertex index
+#[allow(unused)]
+#[derive(Clone, Debug, Default)]
+pub struct MeshReport {
+    /// Edges shared by more than two triangles
+    pub non_manifold_edges: Vec<(u32, u32)>,
+    /// Edges used by a single triangle
+    pub boundary_edges: Vec<(u32, u32)>,
+    /// Closed chains of boundary edges, as vertex loops
+    pub boundary_loops: Vec<Vec<u32>>,
+    /// Chains of boundary edges that end without closing, as vertex paths.
+    /// These arise where the boundary's orientation is inconsistent
+    pub open_boundary_chains: Vec<Vec<u32>>,
+    /// Edges whose two triangles traverse them in the same direction
+    pub inconsistent_edges: Vec<(u32, u32)>,
+    /// Groups of distinct vertices with equal positions
+    pub duplicate_vertices: Vec<Vec<u32>>,
+    /// Vertices with a NaN or infinite coordinate
+    pub non_finite_positions: Vec<u32>,
+    /// Vertices whose normal has zero (or non-finite) length
+    pub zero_normals: Vec<u32>,
+    /// Triangles referring to a vertex that does not exist
+    pub invalid_indices: Vec<u32>,
+}
+
+#[allow(unused)]
+impl MeshReport {
+    pub fn is_manifold(&self) -> bool {
+        self.non_manifold_edges.is_empty()
+    }
+    pub fn is_closed(&self) -> bool {
+        self.boundary_edges.is_empty()
+    }
+    pub fn is_consistently_oriented(&self) -> bool {
+        self.inconsistent_edges.is_empty()
+    }
+    /// Closed, manifold and consistently oriented
+    pub fn is_watertight(&self) -> bool {
+        self.is_manifold() && self.is_closed() && self.is_consistently_oriented()
+    }
+    /// No structural or numerical problems at all
+    pub fn is_valid(&self) -> bool {
+        self.is_watertight()
+            && self.duplicate_vertices.is_empty()
+            && self.non_finite_positions.is_empty()
+            && self.zero_normals.is_empty()
+            && self.invalid_indices.is_empty()
+    }
+}
+
+/// Chain directed boundary edges into closed loops and open chains. Chains
+/// are started where more boundary edges leave a vertex than arrive, so
+/// each is followed from its beginning. Where several boundary edges leave
+/// the same vertex, they are taken in arbitrary order
+fn boundary_chains(directed: &[(u32, u32)]) -> (Vec<Vec<u32>>, Vec<Vec<u32>>) {
+    let mut next: HashMap<u32, Vec<u32>> = HashMap::new();
+    let mut balance: HashMap<u32, i32> = HashMap::new();
+    for &(a, b) in directed {
+        next.entry(a).or_default().push(b);
+        *balance.entry(a).or_default() += 1;
+        *balance.entry(b).or_default() -= 1;
+    }
+    let heads = directed.iter().map(|e| e.0).filter(|v| balance[v] > 0);
+    let starts = heads.chain(directed.iter().map(|e| e.0)).collect::<Vec<_>>();
+    let (mut loops, mut chains) = (Vec::new(), Vec::new());
+    for start in starts {
+        let mut l = vec![start];
+        let mut v = start;
+        let mut closed = false;
+        while let Some(w) = next.get_mut(&v).and_then(|n| n.pop()) {
+            if w == start {
+                closed = true;
+                break
+            }
+            l.push(w);
+            v = w;
+        }
+        if l.len() > 1 {
+            if closed { loops.push(l) } else { chains.push(l) }
+        }
+    }
+    (loops, chains)
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Check the mesh for topological and numerical problems. Meant for
+    /// welded meshes; with duplicated vertices every edge is a boundary edge
+    pub fn validate(&self) -> MeshReport {
+        let mut report = MeshReport::default();
+        let n = self.vertex_count();
+
+        // Each undirected edge, with the direction each triangle uses it in
+        let mut edges: HashMap<(u32, u32), Vec<bool>> = HashMap::new();
+        for (f, t) in self.indices.chunks_exact(3).enumerate() {
+            if t.iter().any(|&i| i as usize >= n) {
+                report.invalid_indices.push(f as u32);
+                continue
+            }
+            for i in 0..3 {
+                let (a, b) = (t[i], t[(i + 1) % 3]);
+                edges.entry((a.min(b), a.max(b))).or_default().push(a < b);
+            }
+        }
+        let mut directed_boundary = Vec::new();
+        for (&e, dirs) in edges.iter() {
+            match dirs.len() {
+                1 => {
+                    report.boundary_edges.push(e);
+                    directed_boundary.push(if dirs[0] { e } else { (e.1, e.0) });
+                },
+                2 => if dirs[0] == dirs[1] { report.inconsistent_edges.push(e) },
+                _ => report.non_manifold_edges.push(e),
+            }
+        }
+        report.non_manifold_edges.sort_unstable();
+        report.boundary_edges.sort_unstable();
+        report.inconsistent_edges.sort_unstable();
+        directed_boundary.sort_unstable();
+        (report.boundary_loops, report.open_boundary_chains) = boundary_chains(&directed_boundary);
+
+        let mut positions: HashMap<[u32; 3], Vec<u32>> = HashMap::new();
+        for i in 0..n as u32 {
+            let p = self.position(i);
+            if !(p.x.is_finite() && p.y.is_finite() && p.z.is_finite()) {
+                report.non_finite_positions.push(i);
+                continue
+            }
+            positions.entry([(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()])
+                .or_default()
+                .push(i);
+        }
+        report.duplicate_vertices = positions.into_values().filter(|g| g.len() > 1).collect();
+        report.duplicate_vertices.sort_unstable();
+
+        if self.normals.len() == self.vertices.len() {
+            for (i, n) in self.normals.chunks_exact(3).enumerate() {
+                let len = glm::length(&glm::vec3(n[0], n[1], n[2]));
+                if !(len.is_finite() && len > 0.0) {
+                    report.zero_normals.push(i as u32);
+                }
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Distance from the centre of an n + 1 point lattice, over `r`
+    fn sphere(n: usize, r: f64) -> Vec<Vec<Vec<f64>>> {
+        let c = n as f64 / 2.0;
+        (0..=n).map(|i| (0..=n).map(|j| (0..=n).map(|k| {
+            ((i as f64 - c).powi(2) + (j as f64 - c).powi(2) + (k as f64 - c).powi(2)).sqrt() / r
+        }).collect()).collect()).collect()
+    }
+
+    #[test]
+    fn marching_cubes_sphere_is_closed_and_manifold() {
+        let m = crate::mc::marching_cubes((0, 0, 0), 1.0, &sphere(16, 5.3), 1.0).weld();
+        let report = m.validate();
+        assert!(report.is_watertight(), "{:?}", report);
+        assert!(report.is_valid(), "{:?}", report);
+        assert!(report.boundary_loops.is_empty() && report.open_boundary_chains.is_empty());
+    }
+
+    #[test]
+    fn sphere_octant_has_one_boundary_loop() {
+        // The chunk holds one octant of the sphere, bounded by three arcs
+        let m = crate::mc::marching_cubes((0, 0, 0), 1.0, &sphere(32, 5.3)[..17], 1.0).weld();
+        let report = m.validate();
+        assert!(report.is_manifold() && !report.is_closed());
+        assert_eq!(report.boundary_loops.len(), 1);
+        assert_eq!(report.boundary_loops[0].len(), report.boundary_edges.len());
+        assert!(report.open_boundary_chains.is_empty());
+    }
+
+    #[test]
+    fn misoriented_boundary_gives_open_chains() {
+        // Two triangles using their shared edge in the same direction
+        let m = Mesh {
+            vertices: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, -1.0, 0.0],
+            indices: vec![0, 1, 2, 0, 1, 3],
+            index_count: 6,
+            ..Default::default()
+        };
+        let report = m.validate();
+        assert_eq!(report.inconsistent_edges, vec![(0, 1)]);
+        assert!(report.boundary_loops.is_empty());
+        let mut chains = report.open_boundary_chains.clone();
+        chains.sort();
+        assert_eq!(chains, vec![vec![1, 2, 0], vec![1, 3, 0]]);
+    }
+}
@@ -0,0 +1,625 @@
+// Sampled volumes and their binary file format, replacing points.txt
+//
+// A file is a 68 byte header followed by the samples, all little endian:
+//
+//   offset  size  field
+//        0     4  magic, "MCVL"
+//        4     2  version, u16, currently 1
+//        6     1  sample type, u8: 0 f32, 1 f64, 2 u8, 3 u16
+//        7     1  compression, u8: 0 none, 1 zlib
+//        8    12  dims, 3 x u32
+//       20    12  spacing between samples, 3 x f32
+//       32    12  origin, position of sample (0, 0, 0), 3 x f32
+//       44    16  range, 2 x f64, lowest and highest sample value
+//       60     8  payload length in bytes, u64
+//       68     -  payload
+//
+// Samples are ordered like `points[i][j][k]`, with k changing fastest.
+// Integer samples map 0 and their maximum linearly onto the range. With zlib
+// compression the samples' bytes are grouped by significance before
+// deflating, all first bytes, then all second bytes and so on, which
+// compresses smooth fields much better than the samples as they are
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use flate2::{bufread::ZlibDecoder, write::ZlibEncoder};
+use rayon::prelude::*;
+
+use crate::field::Grid;
+use crate::mc::{marching_cubes, Mesh};
+use crate::merge::merge_chunks;
+
+pub const MAGIC: [u8; 4] = *b"MCVL";
+pub const VERSION: u16 = 1;
+/// Bytes before the payload
+#[allow(unused)]
+pub const HEADER_SIZE: usize = 68;
+/// Cells per axis handled by one `marching_cubes` call
+pub const CHUNK: usize = 16;
+
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SampleType {
+    #[default]
+    F32,
+    F64,
+    /// Quantised over the header's range
+    U8,
+    /// Quantised over the header's range
+    U16,
+}
+
+#[allow(unused)]
+impl SampleType {
+    /// Bytes per sample
+    pub fn size(self) -> usize {
+        match self {
+            SampleType::F32 => 4,
+            SampleType::F64 => 8,
+            SampleType::U8 => 1,
+            SampleType::U16 => 2,
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            SampleType::F32 => 0,
+            SampleType::F64 => 1,
+            SampleType::U8 => 2,
+            SampleType::U16 => 3,
+        }
+    }
+
+    fn from_code(code: u8) -> io::Result<Self> {
+        match code {
+            0 => Ok(SampleType::F32),
+            1 => Ok(SampleType::F64),
+            2 => Ok(SampleType::U8),
+            3 => Ok(SampleType::U16),
+            _ => Err(invalid(format!("unknown sample type {}", code))),
+        }
+    }
+}
+
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    #[default]
+    Zlib,
+}
+
+#[allow(unused)]
+impl Compression {
+    fn code(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zlib => 1,
+        }
+    }
+
+    fn from_code(code: u8) -> io::Result<Self> {
+        match code {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Zlib),
+            _ => Err(invalid(format!("unknown compression {}", code))),
+        }
+    }
+}
+
+/// How `SampledVolume::write` stores the samples
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WriteOptions {
+    pub sample_type: SampleType,
+    pub compression: Compression,
+}
+
+/// Everything in a volume file before the payload
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VolumeHeader {
+    pub version: u16,
+    pub sample_type: SampleType,
+    pub compression: Compression,
+    pub dims: [usize; 3],
+    pub spacing: glm::Vec3,
+    pub origin: glm::Vec3,
+    pub range: (f64, f64),
+    /// Bytes of sample data following the header, as stored
+    pub payload_len: u64,
+}
+
+/// Samples on a regular lattice laid out as `marching_cubes` expects, with
+/// lattice point (i,j,k) at `origin + (i,j,k) * spacing`
+#[allow(unused)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SampledVolume {
+    /// `points[i][j][k]`, which must not be ragged
+    pub points: Vec<Vec<Vec<f64>>>,
+    pub spacing: glm::Vec3,
+    pub origin: glm::Vec3,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Product of the dims, or an error where it would overflow
+fn sample_count(dims: [usize; 3]) -> io::Result<usize> {
+    dims.iter().try_fold(1usize, |n, &d| n.checked_mul(d))
+        .ok_or_else(|| invalid(format!("volume of {:?} samples is too large", dims)))
+}
+
+/// Group the bytes of `size` byte samples by their position in the sample
+fn shuffle(bytes: &[u8], size: usize) -> Vec<u8> {
+    let n = bytes.len() / size;
+    (0..size).flat_map(|b| (0..n).map(move |i| bytes[i * size + b])).collect()
+}
+
+/// Samples in the order of the file format, as a `points[i][j][k]` lattice
+fn nest(dims: [usize; 3], samples: &[f64]) -> Vec<Vec<Vec<f64>>> {
+    (0..dims[0]).map(|i| (0..dims[1]).map(|j| {
+        samples[(i * dims[1] + j) * dims[2]..][..dims[2]].to_vec()
+    }).collect()).collect()
+}
+
+fn unshuffle(bytes: &[u8], size: usize) -> Vec<u8> {
+    let n = bytes.len() / size;
+    (0..n).flat_map(|i| (0..size).map(move |b| bytes[b * n + i])).collect()
+}
+
+#[allow(unused)]
+impl VolumeHeader {
+    pub fn read<R: Read>(input: &mut R) -> io::Result<Self> {
+        if read_array::<4>(input)? != MAGIC {
+            return Err(invalid("not a volume file".to_string()));
+        }
+        let version = u16::from_le_bytes(read_array(input)?);
+        if version == 0 || version > VERSION {
+            return Err(invalid(format!("unsupported volume version {}", version)));
+        }
+        let [sample_type, compression] = read_array(input)?;
+        let sample_type = SampleType::from_code(sample_type)?;
+        let compression = Compression::from_code(compression)?;
+        let mut dims = [0; 3];
+        for d in dims.iter_mut() {
+            *d = u32::from_le_bytes(read_array(input)?) as usize;
+        }
+        let mut vec3 = || -> io::Result<glm::Vec3> {
+            let mut v = glm::Vec3::zeros();
+            for c in v.iter_mut() {
+                *c = f32::from_le_bytes(read_array(input)?);
+            }
+            Ok(v)
+        };
+        let spacing = vec3()?;
+        let origin = vec3()?;
+        let range = (f64::from_le_bytes(read_array(input)?), f64::from_le_bytes(read_array(input)?));
+        let payload_len = u64::from_le_bytes(read_array(input)?);
+        Ok(VolumeHeader { version, sample_type, compression, dims, spacing, origin, range, payload_len })
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(&MAGIC)?;
+        out.write_all(&self.version.to_le_bytes())?;
+        out.write_all(&[self.sample_type.code(), self.compression.code()])?;
+        for &d in &self.dims {
+            let d = u32::try_from(d).map_err(|_| invalid(format!("dimension {} does not fit in u32", d)))?;
+            out.write_all(&d.to_le_bytes())?;
+        }
+        for c in self.spacing.iter().chain(self.origin.iter()) {
+            out.write_all(&c.to_le_bytes())?;
+        }
+        out.write_all(&self.range.0.to_le_bytes())?;
+        out.write_all(&self.range.1.to_le_bytes())?;
+        out.write_all(&self.payload_len.to_le_bytes())
+    }
+}
+
+#[allow(unused)]
+impl SampledVolume {
+    /// Volume with every sample `f(i, j, k)`
+    pub fn from_fn(
+        dims: [usize; 3],
+        spacing: glm::Vec3,
+        origin: glm::Vec3,
+        f: impl Fn(usize, usize, usize) -> f64,
+    ) -> Self {
+        let points = (0..dims[0]).map(|i|
+            (0..dims[1]).map(|j| (0..dims[2]).map(|k| f(i, j, k)).collect()).collect()
+        ).collect();
+        SampledVolume { points, spacing, origin }
+    }
+
+    /// Number of lattice points along each axis
+    pub fn dims(&self) -> [usize; 3] {
+        let (nx, ny, nz) = Grid::new(&self.points, 1.0).dims();
+        [nx, ny, nz]
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples().count()
+    }
+
+    /// Every sample, with k changing fastest
+    pub fn samples(&self) -> impl Iterator<Item = f64> + '_ {
+        self.points.iter().flatten().flatten().copied()
+    }
+
+    pub fn get(&self, i: usize, j: usize, k: usize) -> f64 {
+        self.points[i][j][k]
+    }
+
+    /// Lowest and highest sample, or (0, 0) if there are none
+    pub fn range(&self) -> (f64, f64) {
+        if self.sample_count() == 0 { return (0.0, 0.0) }
+        self.samples().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), s| (lo.min(s), hi.max(s)))
+    }
+
+    /// The spacing, which `marching_cubes` needs to be the same along every
+    /// axis. Fails if it is not
+    pub fn scale(&self) -> io::Result<f32> {
+        let s = self.spacing;
+        if s.x == s.y && s.y == s.z && s.x > 0.0 {
+            Ok(s.x)
+        } else {
+            Err(invalid(format!("spacing {:?} is not the same positive value on every axis", s.as_slice())))
+        }
+    }
+
+    /// The volume as a scalar field, in lattice space without `origin`.
+    /// Fails as `scale` does
+    pub fn grid(&self) -> io::Result<Grid<'_>> {
+        Ok(Grid::new(&self.points, self.scale()?))
+    }
+
+    /// Chunks of `CHUNK` cells along each axis. Fails unless every axis
+    /// has a whole number of them, as `marching_cubes` reads whole chunks
+    pub fn chunk_counts(&self) -> io::Result<[usize; 3]> {
+        let dims = self.dims();
+        if dims.iter().any(|&n| !n.saturating_sub(1).is_multiple_of(CHUNK)) {
+            return Err(invalid(format!("dims {:?} are not a whole number of {} cell chunks", dims, CHUNK)));
+        }
+        Ok(dims.map(|n| n.saturating_sub(1) / CHUNK))
+    }
+
+    /// Voxel coordinates to pass to `marching_cubes` to cover the volume.
+    /// Fails as `chunk_counts` does
+    pub fn chunk_origins(&self) -> io::Result<Vec<(usize, usize, usize)>> {
+        let [cx, cy, cz] = self.chunk_counts()?;
+        let mut origins = Vec::new();
+        for i in 0..cx {
+            for j in 0..cy {
+                for k in 0..cz {
+                    origins.push((i * CHUNK, j * CHUNK, k * CHUNK));
+                }
+            }
+        }
+        Ok(origins)
+    }
+
+    /// Extract the whole volume as one welded mesh in world space. Fails as
+    /// `scale` and `chunk_counts` do
+    pub fn extract(&self, isolevel: f64) -> io::Result<Mesh> {
+        let scale = self.scale()?;
+        let origins = self.chunk_origins()?;
+        let chunks = origins.par_iter()
+            .map(|&c| marching_cubes(c, scale, &self.points, isolevel))
+            .collect::<Vec<_>>();
+        let mut mesh = merge_chunks(&chunks);
+        for p in mesh.vertices.chunks_exact_mut(3) {
+            for (x, o) in p.iter_mut().zip(self.origin.iter()) {
+                *x += o;
+            }
+        }
+        Ok(mesh)
+    }
+
+    /// Write the header and samples. Integer sample types round to the
+    /// nearest of their steps over `range`, so lose precision
+    pub fn write<W: Write>(&self, out: &mut W, opts: &WriteOptions) -> io::Result<()> {
+        let dims = self.dims();
+        if self.sample_count() != sample_count(dims)? {
+            return Err(invalid(format!("{} samples in a ragged lattice of dims {:?}", self.sample_count(), dims)));
+        }
+        let (lo, hi) = self.range();
+        let quantize = |s: f64, max: f64| if hi > lo { ((s - lo) / (hi - lo) * max).round() } else { 0.0 };
+        let mut bytes = Vec::with_capacity(self.sample_count() * opts.sample_type.size());
+        for s in self.samples() {
+            match opts.sample_type {
+                SampleType::F32 => bytes.extend_from_slice(&(s as f32).to_le_bytes()),
+                SampleType::F64 => bytes.extend_from_slice(&s.to_le_bytes()),
+                SampleType::U8 => bytes.push(quantize(s, u8::MAX as f64) as u8),
+                SampleType::U16 => bytes.extend_from_slice(&(quantize(s, u16::MAX as f64) as u16).to_le_bytes()),
+            }
+        }
+        let payload = match opts.compression {
+            Compression::None => bytes,
+            Compression::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&shuffle(&bytes, opts.sample_type.size()))?;
+                encoder.finish()?
+            }
+        };
+        VolumeHeader {
+            version: VERSION,
+            sample_type: opts.sample_type,
+            compression: opts.compression,
+            dims,
+            spacing: self.spacing,
+            origin: self.origin,
+            range: (lo, hi),
+            payload_len: payload.len() as u64,
+        }.write(out)?;
+        out.write_all(&payload)
+    }
+
+    /// Read a volume that fills the whole input. Fails on truncated
+    /// payloads, on data after the payload and on payloads holding more or
+    /// fewer samples than the dims
+    pub fn read<R: Read>(input: &mut R) -> io::Result<Self> {
+        let header = VolumeHeader::read(input)?;
+        let size = header.sample_type.size();
+        let len = sample_count(header.dims)?.checked_mul(size)
+            .ok_or_else(|| invalid(format!("volume of {:?} samples is too large", header.dims)))?;
+        let mut payload = Vec::new();
+        input.take(header.payload_len).read_to_end(&mut payload)?;
+        if payload.len() as u64 != header.payload_len {
+            return Err(invalid(format!("expected {} bytes of payload, found {}", header.payload_len, payload.len())));
+        }
+        if input.read(&mut [0])? != 0 {
+            return Err(invalid("data after the payload".to_string()));
+        }
+        let mut bytes = match header.compression {
+            Compression::None => payload,
+            Compression::Zlib => {
+                // Read one byte past the expected length to catch overlong data
+                let mut bytes = Vec::new();
+                let mut decoder = ZlibDecoder::new(&payload[..]);
+                (&mut decoder).take(len as u64 + 1).read_to_end(&mut bytes)?;
+                if decoder.total_in() != header.payload_len {
+                    return Err(invalid("data after the compressed samples".to_string()));
+                }
+                bytes
+            }
+        };
+        if bytes.len() != len {
+            return Err(invalid(format!("expected {} bytes of samples, found {}", len, bytes.len())));
+        }
+        if header.compression == Compression::Zlib {
+            bytes = unshuffle(&bytes, size);
+        }
+
+        let (lo, hi) = header.range;
+        let samples = bytes.chunks_exact(size).map(|b| match header.sample_type {
+            SampleType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            SampleType::F64 => f64::from_le_bytes(b.try_into().unwrap()),
+            SampleType::U8 => lo + b[0] as f64 / u8::MAX as f64 * (hi - lo),
+            SampleType::U16 => lo + u16::from_le_bytes([b[0], b[1]]) as f64 / u16::MAX as f64 * (hi - lo),
+        }).collect::<Vec<_>>();
+        Ok(SampledVolume { points: nest(header.dims, &samples), spacing: header.spacing, origin: header.origin })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, opts: &WriteOptions) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out, opts)?;
+        out.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        SampledVolume::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Parse the legacy text format: a line with the three dims, then the
+    /// samples separated by whitespace, in the order of `samples`. The
+    /// text does not record spacing or origin, so they are given
+    pub fn read_legacy_text<R: Read>(input: &mut R, spacing: glm::Vec3, origin: glm::Vec3) -> io::Result<Self> {
+        let mut text = String::new();
+        input.read_to_string(&mut text)?;
+        let (first, rest) = text.split_once('\n').unwrap_or((&text, ""));
+        let dims = first.split_ascii_whitespace()
+            .map(|s| s.parse::<usize>().map_err(|e| invalid(format!("bad dimension {:?}: {}", s, e))))
+            .collect::<io::Result<Vec<_>>>()?;
+        let dims: [usize; 3] = dims.try_into()
+            .map_err(|d: Vec<usize>| invalid(format!("expected 3 dimensions, found {}", d.len())))?;
+        let samples = rest.split_ascii_whitespace()
+            .map(|s| s.parse::<f64>().map_err(|e| invalid(format!("bad sample {:?}: {}", s, e))))
+            .collect::<io::Result<Vec<_>>>()?;
+        if samples.len() != sample_count(dims)? {
+            return Err(invalid(format!("expected {} samples, found {}", sample_count(dims)?, samples.len())));
+        }
+        Ok(SampledVolume { points: nest(dims, &samples), spacing, origin })
+    }
+}
+
+/// Convert a legacy `points.txt` at `src` to a volume file at `dst`,
+/// returning the volume
+#[allow(unused)]
+pub fn convert_legacy<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+    spacing: glm::Vec3,
+    origin: glm::Vec3,
+    opts: &WriteOptions,
+) -> io::Result<SampledVolume> {
+    let volume = SampledVolume::read_legacy_text(&mut BufReader::new(File::open(src)?), spacing, origin)?;
+    volume.save(dst, opts)?;
+    Ok(volume)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_TYPES: [SampleType; 4] = [SampleType::F32, SampleType::F64, SampleType::U8, SampleType::U16];
+    const COMPRESSIONS: [Compression; 2] = [Compression::None, Compression::Zlib];
+
+    /// Smooth field with irrational values, so no sample type stores it exactly
+    fn field() -> SampledVolume {
+        SampledVolume::from_fn([5, 6, 7], glm::vec3(0.5, 1.0, 2.0), glm::vec3(-1.0, 0.0, 3.0), |i, j, k|
+            (i as f64 * 0.7).sin() + (j as f64 / 3.0).sqrt() - k as f64 * std::f64::consts::E
+        )
+    }
+
+    fn encode(volume: &SampledVolume, sample_type: SampleType, compression: Compression) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        volume.write(&mut bytes, &WriteOptions { sample_type, compression }).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let volume = field();
+        let (lo, hi) = volume.range();
+        for sample_type in SAMPLE_TYPES {
+            for compression in COMPRESSIONS {
+                let bytes = encode(&volume, sample_type, compression);
+                let header = VolumeHeader::read(&mut &bytes[..]).unwrap();
+                assert_eq!((header.sample_type, header.compression), (sample_type, compression));
+                assert_eq!(bytes.len(), HEADER_SIZE + header.payload_len as usize);
+
+                let read = SampledVolume::read(&mut &bytes[..]).unwrap();
+                assert_eq!((read.dims(), read.spacing, read.origin), (volume.dims(), volume.spacing, volume.origin));
+                let tolerance = match sample_type {
+                    SampleType::F64 => 0.0,
+                    SampleType::F32 => 1e-6 * lo.abs().max(hi.abs()),
+                    // Half a step over the range, with room for rounding
+                    SampleType::U8 => (hi - lo) / (2.0 * u8::MAX as f64) * 1.0001,
+                    SampleType::U16 => (hi - lo) / (2.0 * u16::MAX as f64) * 1.0001,
+                };
+                for (a, b) in read.samples().zip(volume.samples()) {
+                    assert!((a - b).abs() <= tolerance, "{:?} {:?}: {} read back as {}", sample_type, compression, b, a);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn constant_volume_round_trips() {
+        let volume = SampledVolume::from_fn([2, 2, 2], glm::Vec3::repeat(1.0), glm::Vec3::zeros(), |_, _, _| 0.25);
+        for sample_type in SAMPLE_TYPES {
+            let read = SampledVolume::read(&mut &encode(&volume, sample_type, Compression::Zlib)[..]).unwrap();
+            assert_eq!(read, volume);
+        }
+    }
+
+    #[test]
+    fn truncated_files_fail() {
+        for compression in COMPRESSIONS {
+            let bytes = encode(&field(), SampleType::U16, compression);
+            for len in [0, 3, HEADER_SIZE - 1, HEADER_SIZE, bytes.len() - 1] {
+                assert!(SampledVolume::read(&mut &bytes[..len]).is_err(), "{:?} cut to {}", compression, len);
+            }
+        }
+    }
+
+    #[test]
+    fn overlong_files_fail() {
+        for compression in COMPRESSIONS {
+            // Data after the payload
+            let mut bytes = encode(&field(), SampleType::F32, compression);
+            bytes.push(0);
+            assert!(SampledVolume::read(&mut &bytes[..]).is_err());
+
+            // Payload longer than the samples, with its length to match
+            let mut bytes = encode(&field(), SampleType::F32, compression);
+            let payload_len = u64::from_le_bytes(bytes[60..68].try_into().unwrap());
+            bytes[60..68].copy_from_slice(&(payload_len + 4).to_le_bytes());
+            bytes.extend_from_slice(&[0; 4]);
+            assert!(SampledVolume::read(&mut &bytes[..]).is_err());
+        }
+
+        // Compressed samples for larger dims than the header's
+        let mut bytes = encode(&field(), SampleType::F32, Compression::Zlib);
+        bytes[8..12].copy_from_slice(&4u32.to_le_bytes());
+        assert!(SampledVolume::read(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn bad_headers_fail() {
+        let bytes = encode(&field(), SampleType::F32, Compression::None);
+        let corrupt = |at: usize, value: u8| {
+            let mut bytes = bytes.clone();
+            bytes[at] = value;
+            SampledVolume::read(&mut &bytes[..])
+        };
+        assert!(corrupt(0, b'X').is_err());
+        assert!(corrupt(4, 2).is_err());
+        assert!(corrupt(6, 4).is_err());
+        assert!(corrupt(7, 2).is_err());
+        assert!(SampledVolume::read(&mut &b"points 1 1 1\n0.5"[..]).is_err());
+    }
+
+    #[test]
+    fn legacy_text_parses() {
+        let text = "2 1 2\n0.5 -1\n2.25\n3e1\n";
+        let volume = SampledVolume::read_legacy_text(&mut text.as_bytes(), glm::Vec3::repeat(1.0), glm::Vec3::zeros()).unwrap();
+        assert_eq!(volume.dims(), [2, 1, 2]);
+        assert_eq!(volume.samples().collect::<Vec<_>>(), vec![0.5, -1.0, 2.25, 30.0]);
+        assert_eq!(volume.get(1, 0, 0), 2.25);
+        assert_eq!(volume.points, vec![vec![vec![0.5, -1.0]], vec![vec![2.25, 30.0]]]);
+
+        for bad in ["2 1 2\n0.5 -1 2.25\n", "2 1\n0.5 -1\n", "2 1 x\n0.5\n", "1 1 1\nnan?\n"] {
+            assert!(SampledVolume::read_legacy_text(&mut bad.as_bytes(), glm::Vec3::repeat(1.0), glm::Vec3::zeros()).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn ragged_lattices_are_not_written() {
+        let mut volume = field();
+        volume.points[1][2].pop();
+        assert!(volume.write(&mut Vec::new(), &WriteOptions::default()).is_err());
+    }
+
+    #[test]
+    fn extract_places_the_surface_at_the_origin() {
+        // Sphere of radius 5 around the middle of a 33 point lattice
+        let origin = glm::vec3(10.0, -4.0, 2.0);
+        let volume = SampledVolume::from_fn([33, 33, 33], glm::Vec3::repeat(0.5), origin, |i, j, k| {
+            glm::length(&glm::vec3(i as f32 - 16.0, j as f32 - 16.0, k as f32 - 16.0)) as f64 * 0.5 - 5.0
+        });
+        assert_eq!(volume.chunk_origins().unwrap().len(), 8);
+        let mesh = volume.extract(0.0).unwrap();
+        assert!(mesh.validate().is_watertight());
+        let (min, max) = mesh.bounds().unwrap();
+        let centre = origin + glm::Vec3::repeat(8.0);
+        assert!(glm::distance(&((min + max) * 0.5), &centre) < 1e-3);
+        assert!(((max - min).x - 10.0).abs() < 0.1);
+
+        // A voxelized mesh is saved and extracted like any other volume
+        let mut bytes = Vec::new();
+        let voxelized = mesh.voxelize(&crate::voxelize::VoxelizeOptions { resolution: 8, ..Default::default() });
+        voxelized.write(&mut bytes, &WriteOptions { sample_type: SampleType::F64, ..Default::default() }).unwrap();
+        let read = SampledVolume::read(&mut &bytes[..]).unwrap();
+        assert_eq!(read, voxelized);
+        assert!(read.extract(0.0).unwrap().validate().is_watertight());
+    }
+
+    #[test]
+    fn anisotropic_spacing_is_not_extracted() {
+        let mut volume = field();
+        assert!(volume.scale().is_err());
+        assert!(volume.grid().is_err());
+        assert!(volume.extract(0.0).is_err());
+        volume.spacing = glm::Vec3::repeat(0.25);
+        assert_eq!(volume.scale().unwrap(), 0.25);
+    }
+
+    #[test]
+    fn partial_chunks_are_not_extracted() {
+        let volume = SampledVolume::from_fn([17, 33, 20], glm::Vec3::repeat(1.0), glm::Vec3::zeros(), |_, _, _| 1.0);
+        assert!(volume.chunk_counts().is_err());
+        assert!(volume.extract(0.0).is_err());
+        let volume = SampledVolume::from_fn([17, 33, 1], glm::Vec3::repeat(1.0), glm::Vec3::zeros(), |_, _, _| 1.0);
+        assert_eq!(volume.chunk_counts().unwrap(), [1, 2, 0]);
+        assert!(volume.extract(0.0).unwrap().indices.is_empty());
+    }
+}
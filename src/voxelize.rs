@@ -0,0 +1,225 @@
+// Loading OBJ models and converting triangle meshes into sampled volumes
+
+use std::path::Path;
+use rayon::prelude::*;
+
+use crate::bvh::{Bvh, BvhOptions};
+use crate::mc::Mesh;
+use crate::ray::Ray;
+use crate::volume::{SampledVolume, CHUNK};
+
+/// Offset of parity rays from their lattice row, in cells
+const ROW_JITTER: (f32, f32) = (1.234_567e-3, 7.654_321e-4);
+
+/// How lattice points are classified as inside or outside the mesh
+#[allow(unused)]
+#[derive(Clone, Copy, Debug)]
+pub enum SignMethod {
+    /// Generalised winding number, summing the solid angle of every triangle.
+    /// Tolerates holes and self-intersections, but costs a pass over all
+    /// triangles per lattice point
+    WindingNumber,
+    /// Count crossings along rays in +x, +y and +z and take the majority.
+    /// Fast, but needs a closed mesh
+    RayParity,
+}
+
+/// What is stored at each lattice point
+#[allow(unused)]
+#[derive(Clone, Copy, Debug)]
+pub enum VolumeKind {
+    /// Signed distance to the mesh in world units, negative inside.
+    /// Extract at isolevel 0
+    Distance,
+    /// 0 inside, 1 outside. Extract at isolevel 0.5
+    Occupancy,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VoxelizeOptions {
+    /// Cells across the longest side of the mesh bounds
+    pub resolution: usize,
+    /// Cells of empty space around the mesh. At least 1, so the volume
+    /// border is outside
+    pub padding: usize,
+    pub sign: SignMethod,
+    pub kind: VolumeKind,
+}
+impl Default for VoxelizeOptions {
+    fn default() -> Self {
+        VoxelizeOptions {
+            resolution: 64,
+            padding: 2,
+            sign: SignMethod::RayParity,
+            kind: VolumeKind::Distance,
+        }
+    }
+}
+
+/// Solid angle of the triangle seen from `p`, over 4π, signed by which side
+/// `p` is on (Van Oosterom and Strackee)
+fn winding(p: &glm::Vec3, t: &[glm::Vec3; 3]) -> f64 {
+    let [a, b, c] = t.map(|v| {
+        let d = v - p;
+        glm::vec3(d.x as f64, d.y as f64, d.z as f64)
+    });
+    let (la, lb, lc) = (glm::length(&a), glm::length(&b), glm::length(&c));
+    let det = glm::dot(&a, &glm::cross(&b, &c));
+    let div = la * lb * lc + glm::dot(&a, &b) * lc + glm::dot(&b, &c) * la + glm::dot(&c, &a) * lb;
+    det.atan2(div) / (2.0 * std::f64::consts::PI)
+}
+
+/// Crossings of a ray, with hits at the same distance counted once so rays
+/// through an edge or vertex shared by several triangles are not counted
+/// repeatedly
+fn crossings(bvh: &Bvh, ray: &Ray, max_distance: f32) -> Vec<f32> {
+    let mut t = bvh.raycast_all(ray, max_distance).into_iter().map(|h| h.0).collect::<Vec<_>>();
+    t.sort_unstable_by(f32::total_cmp);
+    t.dedup_by(|a, b| (*a - *b).abs() <= 1e-6 * b.abs().max(1.0));
+    t
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Load every model in an OBJ file into one mesh. Faces are triangulated,
+    /// and normals are computed if the file has none
+    pub fn load_obj<P: AsRef<Path> + std::fmt::Debug>(path: P) -> Result<Mesh, tobj::LoadError> {
+        let (models, _materials) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)?;
+        let mut mesh = Mesh::new();
+        let has_normals = models.iter().all(|m| !m.mesh.normals.is_empty());
+        let has_texcoords = models.iter().all(|m| !m.mesh.texcoords.is_empty());
+        for m in models.iter().map(|m| &m.mesh) {
+            let base = mesh.vertex_count() as u32;
+            mesh.vertices.extend_from_slice(&m.positions);
+            if has_normals { mesh.normals.extend_from_slice(&m.normals) }
+            if has_texcoords { mesh.texture_coordinates.extend_from_slice(&m.texcoords) }
+            mesh.indices.extend(m.indices.iter().map(|i| base + i));
+        }
+        mesh.index_count = mesh.indices.len() as i32;
+        if !has_normals { mesh.recompute_normals() }
+        Ok(mesh)
+    }
+
+    /// Sample the mesh into a volume with the same spacing on every axis.
+    /// Each axis has a multiple of `CHUNK` cells so the volume splits evenly
+    /// into chunks. The mesh should be closed for the inside to be well
+    /// defined; `SignMethod::WindingNumber` degrades gracefully when it is not
+    pub fn voxelize(&self, opts: &VoxelizeOptions) -> SampledVolume {
+        let (min, max) = self.bounds().unwrap_or((glm::zero(), glm::zero()));
+        let extent = max - min;
+        let scale = extent.max().max(f32::EPSILON) / opts.resolution.max(1) as f32;
+        let padding = opts.padding.max(1);
+        let origin = min - glm::Vec3::repeat(padding as f32 * scale);
+        // Cells per axis, rounded up to whole chunks
+        let cells = extent.map(|e| {
+            let n = (e / scale).ceil() as usize + 2 * padding;
+            n.div_ceil(CHUNK) * CHUNK
+        });
+        let (nx, ny, nz) = (cells.x + 1, cells.y + 1, cells.z + 1);
+        let at = |i: usize, j: usize, k: usize| origin + glm::vec3(i as f32, j as f32, k as f32) * scale;
+
+        let bvh = Bvh::build(self, &BvhOptions::default());
+        let triangles = (0..self.indices.len() as u32 / 3).map(|f| *bvh.triangle(f)).collect::<Vec<_>>();
+        let far = glm::length(&(cells.cast::<f32>() * scale)) * 2.0;
+
+        // Inside/outside per lattice point, in x-major order
+        let inside: Vec<bool> = match opts.sign {
+            SignMethod::WindingNumber => (0..nx * ny * nz).into_par_iter().map(|n| {
+                let p = at(n / (ny * nz), (n / nz) % ny, n % nz);
+                triangles.iter().map(|t| winding(&p, t)).sum::<f64>().abs() > 0.5
+            }).collect(),
+            SignMethod::RayParity => {
+                // Votes per lattice point, from one ray per lattice row along
+                // each axis
+                let dims = [nx, ny, nz];
+                let index = |p: [usize; 3]| (p[0] * ny + p[1]) * nz + p[2];
+                let mut votes = vec![0u8; nx * ny * nz];
+                for axis in 0..3 {
+                    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                    let rows = (0..dims[u] * dims[v]).into_par_iter().map(|r| {
+                        let mut p = [0; 3];
+                        p[u] = r / dims[v];
+                        p[v] = r % dims[v];
+                        // Nudge the ray off the row by an irregular fraction of
+                        // a cell, so it does not run exactly through vertices
+                        // and edges of meshes aligned with the lattice
+                        let mut start = at(p[0], p[1], p[2]);
+                        start[u] += ROW_JITTER.0 * scale;
+                        start[v] += ROW_JITTER.1 * scale;
+                        let mut dir = glm::Vec3::zeros();
+                        dir[axis] = 1.0;
+                        let hits = crossings(&bvh, &Ray::new(start, dir), far);
+                        // Odd number of crossings ahead means inside
+                        (0..dims[axis]).map(|s| {
+                            let t = s as f32 * scale;
+                            (hits.len() - hits.partition_point(|&h| h < t)) % 2 == 1
+                        }).collect::<Vec<_>>()
+                    }).collect::<Vec<_>>();
+                    for (r, row) in rows.into_iter().enumerate() {
+                        let mut p = [0; 3];
+                        p[u] = r / dims[v];
+                        p[v] = r % dims[v];
+                        for (s, inside) in row.into_iter().enumerate() {
+                            p[axis] = s;
+                            votes[index(p)] += inside as u8;
+                        }
+                    }
+                }
+                votes.into_iter().map(|v| v >= 2).collect()
+            },
+        };
+
+        let values: Vec<f64> = (0..nx * ny * nz).into_par_iter().map(|n| {
+            match opts.kind {
+                VolumeKind::Occupancy => if inside[n] { 0.0 } else { 1.0 },
+                VolumeKind::Distance => {
+                    let p = at(n / (ny * nz), (n / nz) % ny, n % nz);
+                    let d = bvh.closest_point(&p, f32::INFINITY).map_or(far, |c| c.distance) as f64;
+                    if inside[n] { -d } else { d }
+                },
+            }
+        }).collect();
+        let points = (0..nx).map(|i| (0..ny).map(|j| {
+            values[(i * ny + j) * nz..(i * ny + j + 1) * nz].to_vec()
+        }).collect()).collect();
+        SampledVolume { points, spacing: glm::Vec3::repeat(scale), origin }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Voxelize and extract `mesh`, checking the result against it
+    fn round_trip(mesh: &Mesh, sign: SignMethod, kind: VolumeKind, volume_tolerance: f64) {
+        let opts = VoxelizeOptions { resolution: 10, sign, kind, ..Default::default() };
+        let volume = mesh.voxelize(&opts);
+        let isolevel = match kind { VolumeKind::Distance => 0.0, VolumeKind::Occupancy => 0.5 };
+        let extracted = volume.extract(isolevel).unwrap();
+
+        let report = extracted.validate();
+        assert!(report.is_watertight(), "{:?} {:?}: {:?}", sign, kind, report);
+        let (expected, got) = (mesh.volume(), extracted.volume());
+        assert!((got / expected - 1.0).abs() < volume_tolerance, "{:?} {:?}: volume {} of {}", sign, kind, got, expected);
+        let (min, max) = mesh.bounds().unwrap();
+        let (emin, emax) = extracted.bounds().unwrap();
+        let error = glm::max2(&(emin - min).abs(), &(emax - max).abs()).max();
+        assert!(error < volume.spacing.x, "{:?} {:?}: bounds off by {}", sign, kind, error);
+    }
+
+    #[test]
+    fn icosphere_round_trip() {
+        let sphere = Mesh::icosphere(2.0, 2).weld();
+        round_trip(&sphere, SignMethod::WindingNumber, VolumeKind::Distance, 0.06);
+        round_trip(&sphere, SignMethod::RayParity, VolumeKind::Distance, 0.06);
+        round_trip(&sphere, SignMethod::RayParity, VolumeKind::Occupancy, 0.15);
+    }
+
+    #[test]
+    fn box_round_trip() {
+        // Four sided prism, a box turned 45 degrees about y
+        let prism = Mesh::cylinder(1.5, 2.0, 4, true).weld();
+        round_trip(&prism, SignMethod::WindingNumber, VolumeKind::Distance, 0.06);
+        round_trip(&prism, SignMethod::RayParity, VolumeKind::Distance, 0.06);
+    }
+}
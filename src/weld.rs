@@ -0,0 +1,119 @@
+// Welding duplicated vertices into an indexed mesh
+
+use std::collections::HashMap;
+use crate::mc::Mesh;
+
+/// Hash key for a position. `+ 0.0` folds -0.0 into 0.0
+fn position_key(p: glm::Vec3) -> [u32; 3] {
+    [(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()]
+}
+
+#[allow(unused)]
+impl Mesh {
+    /// Merge vertices with equal positions into one, giving an indexed mesh
+    /// with smooth normals. Marching cubes output welds exactly, as shared
+    /// edges produce bitwise equal vertices. Triangles left with a repeated
+    /// index are dropped. Texture coordinates, colours and occlusion are taken
+    /// from the first vertex merged. As in `compact`, an attribute that does
+    /// not match the vertex count is dropped
+    pub fn weld(&self) -> Mesh {
+        let n = self.vertex_count();
+        let mut lookup = HashMap::with_capacity(n);
+        let mut remap = Vec::with_capacity(n);
+        let mut first = Vec::new();
+        for i in 0..n {
+            let id = *lookup.entry(position_key(self.position(i as u32))).or_insert_with(|| {
+                first.push(i);
+                first.len() as u32 - 1
+            });
+            remap.push(id);
+        }
+
+        let mut indices = Vec::with_capacity(self.indices.len());
+        for t in self.indices.chunks_exact(3) {
+            let t = [remap[t[0] as usize], remap[t[1] as usize], remap[t[2] as usize]];
+            if t[0] != t[1] && t[1] != t[2] && t[2] != t[0] {
+                indices.extend_from_slice(&t);
+            }
+        }
+
+        let gather = |attr: &Vec<f32>| -> Vec<f32> {
+            if n == 0 { return Vec::new() }
+            if !attr.len().is_multiple_of(n) {
+                return Vec::new();
+            }
+            let k = attr.len() / n;
+            first.iter().flat_map(|&v| attr[v*k..v*k+k].to_vec()).collect()
+        };
+        let mut mesh = Mesh {
+            vertices: gather(&self.vertices),
+            texture_coordinates: gather(&self.texture_coordinates),
+            colors: gather(&self.colors),
+            occlusion: gather(&self.occlusion),
+            index_count: indices.len() as i32,
+            indices,
+            ..Default::default()
+        };
+        mesh.compact();
+        mesh.recompute_normals();
+        mesh
+    }
+
+    /// Per-vertex normals as the area weighted average of the adjacent face
+    /// normals. On a mesh with duplicated vertices this gives flat shading
+    pub fn recompute_normals(&mut self) {
+        let mut normals = vec![glm::Vec3::zeros(); self.vertex_count()];
+        for t in self.indices.chunks_exact(3) {
+            let (a, b, c) = (self.position(t[0]), self.position(t[1]), self.position(t[2]));
+            // Equal to cross(b - a, c - a), as used by marching cubes
+            let n = glm::cross(&(a - b), &(a - c));
+            for &i in t {
+                normals[i as usize] += n;
+            }
+        }
+        self.normals = normals.iter()
+            .flat_map(|n| {
+                let n = if glm::length2(n) > 0.0 { glm::normalize(n) } else { *n };
+                [n.x, n.y, n.z]
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two triangles sharing an edge, with the edge's vertices duplicated
+    fn split_quad() -> Mesh {
+        Mesh {
+            vertices: vec![
+                0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0,
+                -0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0,
+            ],
+            occlusion: vec![0.1, 0.2, 0.3, 0.4, 0.5, 0.6],
+            indices: vec![0, 1, 2, 3, 4, 5],
+            index_count: 6,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn weld_merges_equal_positions() {
+        let mesh = split_quad().weld();
+        assert_eq!(mesh.vertex_count(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.occlusion, vec![0.1, 0.2, 0.3, 0.6]);
+        assert_eq!(mesh.normals.len(), 12);
+    }
+
+    #[test]
+    fn weld_drops_mismatched_attributes() {
+        let mut mesh = split_quad();
+        mesh.colors = vec![1.0; 5];
+        let welded = mesh.weld();
+        assert!(welded.colors.is_empty());
+        assert_eq!(welded.occlusion, vec![0.1, 0.2, 0.3, 0.6]);
+        assert_eq!(welded.validate_attributes(), Ok(4));
+    }
+}